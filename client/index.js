@@ -10,7 +10,7 @@ const params = new URLSearchParams(window.location.search);
 
 const model_name = params.has('model-name') ? params.get('model-name') : "Head";
 
-const camera_speed = params.has('camera-speed') ? params.get('camera-speed') : 0.05;
+const camera_speed = params.has('camera-speed') ? params.get('camera-speed') : 3;
 
 const rotation_speed = params.has('rotation-speed') ? params.get('rotation-speed') : 0.1;
 
@@ -21,6 +21,45 @@ const use_normal_map = params.has('normal-map') ? params.get('normal-map') === "
 const use_diffuse_map = params.has('diffuse-map') ? params.get('diffuse-map') === "true" : true;
 const use_specular_map = params.has('specular-map') ? params.get('specular-map') === "true" : true;
 const use_emission_map = params.has('emission-map') ? params.get('emission-map') === "true" : true;
+const use_metallic_map = params.has('metallic-map') ? params.get('metallic-map') === "true" : true;
+const use_roughness_map = params.has('roughness-map') ? params.get('roughness-map') === "true" : true;
+const use_ao_map = params.has('ao-map') ? params.get('ao-map') === "true" : true;
+
+// path of the radiance .hdr file under /source used for the image based lighting
+const environment_map = params.has('environment-map') ? params.get('environment-map') : null;
+const environment_intensity = params.has('environment-intensity') ? params.get('environment-intensity') : 1;
+
+// 0 - none, 1 - reinhard, 2 - extended reinhard, 3 - aces filmic, 4 - agx
+const tone_mapping = params.has('tone-mapping') ? params.get('tone-mapping') : 0;
+const exposure = params.has('exposure') ? params.get('exposure') : 1;
+const srgb_output = params.has('srgb-output') ? params.get('srgb-output') === "true" : true;
+const use_bloom = params.has('bloom') ? params.get('bloom') === "true" : true;
+const bloom_threshold = params.has('bloom-threshold') ? params.get('bloom-threshold') : 1.25;
+const bloom_intensity = params.has('bloom-intensity') ? params.get('bloom-intensity') : 1;
+const use_ssao = params.has('ssao') ? params.get('ssao') === "true" : false;
+const ssao_radius = params.has('ssao-radius') ? params.get('ssao-radius') : 0.5;
+const use_dof = params.has('dof') ? params.get('dof') === "true" : false;
+const focus_distance = params.has('focus-distance') ? params.get('focus-distance') : 3;
+const aperture = params.has('aperture') ? params.get('aperture') : 2.8;
+const autofocus = params.has('autofocus') ? params.get('autofocus') === "true" : false;
+const fog_mode = params.has('fog') ? params.get('fog') : 0;
+const fog_density = params.has('fog-density') ? params.get('fog-density') : 0.1;
+const height_fog = params.has('height-fog') ? params.get('height-fog') : 0;
+const debug_view = params.has('debug-view') ? params.get('debug-view') : 0;
+let projection = params.has('projection') ? Number(params.get('projection')) : 0;
+const ortho_zoom = params.has('ortho-zoom') ? params.get('ortho-zoom') : 1;
+const fov = params.has('fov') ? params.get('fov') : 45;
+const auto_clip = params.has('auto-clip') ? params.get('auto-clip') === "true" : true;
+const z_near = params.has('near') ? params.get('near') : 0.1;
+const z_far = params.has('far') ? params.get('far') : 10;
+const orbit_camera = params.has('camera-controller') ? params.get('camera-controller') === "orbit" : false;
+const orbit_inertia = params.has('orbit-inertia') ? params.get('orbit-inertia') : 0.06;
+const auto_frame = params.has('auto-frame') ? params.get('auto-frame') === "true" : true;
+const turntable_speed = params.has('turntable-speed') ? params.get('turntable-speed') : 1;
+
+// 1 - phong, 2 - pbr
+const shading_model = params.has('shading-model') ? params.get('shading-model') === "pbr" ? 2 : 1 : 1;
+const use_shadows = params.has('shadows') ? params.get('shadows') === "true" : true;
 
 
 let model_rotation = params.has('model-rotation') ? params.get('model-rotation') === "true" : false;
@@ -64,8 +103,66 @@ let model_rotation = params.has('model-rotation') ? params.get('model-rotation')
         emissionMapImage = await ImageJS.load(await emissionMapRes.arrayBuffer())
     }
 
+    let metallicMapRes = await fetch(`/source/${model_name}/Metallic map.png`);
+    let metallicMapImage = null;
+    if (metallicMapRes.ok) {
+        metallicMapImage = await ImageJS.load(await metallicMapRes.arrayBuffer())
+    }
+
+    let roughnessMapRes = await fetch(`/source/${model_name}/Roughness map.png`);
+    let roughnessMapImage = null;
+    if (roughnessMapRes.ok) {
+        roughnessMapImage = await ImageJS.load(await roughnessMapRes.arrayBuffer())
+    }
+
+    let aoMapRes = await fetch(`/source/${model_name}/AO map.png`);
+    let aoMapImage = null;
+    if (aoMapRes.ok) {
+        aoMapImage = await ImageJS.load(await aoMapRes.arrayBuffer())
+    }
+
     // core stuff
     const image = wasm.Image.new(width, height);
+    image.set_tone_mapping(tone_mapping);
+    image.set_tone_mapping_param(1, exposure);
+    image.set_tone_mapping_param(4, srgb_output ? 1 : 0);
+    image.set_bloom_param(1, use_bloom ? 1 : 0);
+    image.set_bloom_param(2, bloom_threshold);
+    image.set_bloom_param(4, bloom_intensity);
+    image.set_ssao_param(1, use_ssao ? 1 : 0);
+    image.set_ssao_param(2, ssao_radius);
+    image.set_depth_of_field_param(1, use_dof ? 1 : 0);
+    image.set_camera_param(21, focus_distance);
+    image.set_camera_param(22, aperture);
+    image.set_camera_param(24, autofocus ? 1 : 0);
+    image.set_fog_mode(fog_mode);
+    image.set_fog_param(1, fog_density);
+    image.set_fog_param(4, height_fog);
+    image.set_debug_view(debug_view);
+    image.set_camera_param(31, ortho_zoom);
+    image.set_camera_param(41, fov);
+    image.set_camera_param(43, z_far);
+    image.set_camera_param(42, z_near);
+    image.set_camera_param(44, auto_clip ? 1 : 0);
+    image.set_orbit_param(3, orbit_inertia);
+    image.set_camera_controller(orbit_camera ? 1 : 0);
+    image.set_camera_projection(projection);
+
+    if (environment_map) {
+        let environmentRes = await fetch(`/source/${environment_map}`);
+        if (environmentRes.ok) {
+            const environmentData = new Uint8Array(await environmentRes.arrayBuffer());
+            image.set_environment_data_size(environmentData.length);
+            const environmentDataPtr = image.get_environment_data();
+            new Uint8Array(wasm_bg.memory.buffer, environmentDataPtr, environmentData.length).set(environmentData);
+            if (image.load_environment()) {
+                image.set_environment_param(2, environment_intensity);
+                console.log('using environment map');
+            }
+        } else {
+            alert("Cannot fetch environment map");
+        }
+    }
     
 
     // objects
@@ -93,6 +190,7 @@ let model_rotation = params.has('model-rotation') ? params.get('model-rotation')
     });
 
     image.set_object_scale(objHandler1, model_scale);
+    image.set_object_shading_model(objHandler1, shading_model);
 
     if (use_diffuse_map && diffuseMapImage) {
         image.set_object_texture_size(objHandler1, 1, diffuseMapImage.width, diffuseMapImage.height);
@@ -142,6 +240,47 @@ let model_rotation = params.has('model-rotation') ? params.get('model-rotation')
         console.log('not using emission map');
     }
 
+    if (use_metallic_map && metallicMapImage) {
+        image.set_object_texture_size(objHandler1, 5, metallicMapImage.width, metallicMapImage.height);
+        const metallicTexturePixelsPtr = image.get_object_texture_pixels(objHandler1, 5);
+        const metallicTexturePixels = new Uint8ClampedArray(wasm_bg.memory.buffer, metallicTexturePixelsPtr, metallicMapImage.width * metallicMapImage.height * 4);
+        metallicTexturePixels.set(metallicMapImage.getRGBAData({clamped: true}));
+        image.set_object_use_texture(objHandler1, 5, true);
+        console.log('using metallic map');
+    } else {
+        image.set_object_use_texture(objHandler1, 5, false);
+        console.log('not using metallic map');
+    }
+
+    if (use_roughness_map && roughnessMapImage) {
+        image.set_object_texture_size(objHandler1, 6, roughnessMapImage.width, roughnessMapImage.height);
+        const roughnessTexturePixelsPtr = image.get_object_texture_pixels(objHandler1, 6);
+        const roughnessTexturePixels = new Uint8ClampedArray(wasm_bg.memory.buffer, roughnessTexturePixelsPtr, roughnessMapImage.width * roughnessMapImage.height * 4);
+        roughnessTexturePixels.set(roughnessMapImage.getRGBAData({clamped: true}));
+        image.set_object_use_texture(objHandler1, 6, true);
+        console.log('using roughness map');
+    } else {
+        image.set_object_use_texture(objHandler1, 6, false);
+        console.log('not using roughness map');
+    }
+
+    if (use_ao_map && aoMapImage) {
+        image.set_object_texture_size(objHandler1, 7, aoMapImage.width, aoMapImage.height);
+        const aoTexturePixelsPtr = image.get_object_texture_pixels(objHandler1, 7);
+        const aoTexturePixels = new Uint8ClampedArray(wasm_bg.memory.buffer, aoTexturePixelsPtr, aoMapImage.width * aoMapImage.height * 4);
+        aoTexturePixels.set(aoMapImage.getRGBAData({clamped: true}));
+        image.set_object_use_texture(objHandler1, 7, true);
+        console.log('using ao map');
+    } else {
+        image.set_object_use_texture(objHandler1, 7, false);
+        console.log('not using ao map');
+    }
+    image.set_shadow_param(1, use_shadows ? 1 : 0);
+
+    if (auto_frame) {
+        image.frame_all(0);
+    }
+
     // fps stuff
     let lastLoop = new Date();
     let fpsLabel = document.getElementById("fps-label");
@@ -166,13 +305,45 @@ let model_rotation = params.has('model-rotation') ? params.get('model-rotation')
 
     let angle = 0;
     let pressedKeys = {};
+    let turntable = false;
+
+    // closed loop around the scene at the current camera height, looking at its center
+    const startTurntable = () => {
+        const bounds = image.get_world_bounds();
+        if (!bounds)
+            return false;
+        const center = [(bounds.min_x + bounds.max_x) / 2, (bounds.min_y + bounds.max_y) / 2, (bounds.min_z + bounds.max_z) / 2];
+        const size = Math.hypot(bounds.max_x - bounds.min_x, bounds.max_y - bounds.min_y, bounds.max_z - bounds.min_z);
+        bounds.free();
+        const radius = size * 1.5;
+        image.clear_camera_path();
+        for (let i = 0; i < 8; i++) {
+            const a = i / 8 * 2 * Math.PI;
+            image.add_camera_path_point(center[0] + radius * Math.sin(a), center[1] + size * 0.3, center[2] + radius * Math.cos(a));
+        }
+        image.set_camera_path_curve(0);
+        image.set_camera_path_look(1);
+        image.set_camera_path_target(center[0], center[1], center[2]);
+        image.set_camera_path_param(1, turntable_speed * radius);
+        image.set_camera_path_param(2, 1);
+        image.set_camera_path_param(3, 1);
+        image.play_camera_path();
+        return true;
+    }
+
+    let lastTime = performance.now();
+
+    const rotationLoop = (time) => {
+        // seconds since the previous frame, long pauses (hidden tab) do not make the camera jump
+        const dt = Math.min((time - lastTime) / 1000, 0.1);
+        lastTime = time;
+        image.update(dt);
 
-    const rotationLoop = () => {
         if (model_rotation) {
             angle += rotation_speed;
             image.set_object_rotation(objHandler1, 0, angle, 0);
         } 
-        if (!Object.values(pressedKeys).every(v => !v) || model_rotation) {
+        if (!Object.values(pressedKeys).every(v => !v) || model_rotation || image.is_camera_moving()) {
             image.compute();
             render();
             
@@ -186,6 +357,62 @@ let model_rotation = params.has('model-rotation') ? params.get('model-rotation')
 
     requestAnimationFrame(rotationLoop);
 
+    // orbit camera: drag rotates, right or shift drag pans, wheel zooms to the cursor
+    let dragDistance = 0;
+    canvas.addEventListener('contextmenu', (e) => e.preventDefault());
+    canvas.addEventListener('pointerdown', (e) => {
+        dragDistance = 0;
+        canvas.setPointerCapture(e.pointerId);
+    });
+    canvas.addEventListener('pointermove', (e) => {
+        if (!e.buttons || !orbit_camera)
+            return;
+        const rect = canvas.getBoundingClientRect();
+        const dx = e.movementX * width / rect.width;
+        const dy = e.movementY * height / rect.height;
+        dragDistance += Math.abs(dx) + Math.abs(dy);
+        if (e.buttons & 2 || e.shiftKey) {
+            image.orbit_pan(dx, dy);
+        } else {
+            image.orbit_rotate(dx, dy);
+        }
+    });
+    canvas.addEventListener('wheel', (e) => {
+        if (!orbit_camera)
+            return;
+        e.preventDefault();
+        const rect = canvas.getBoundingClientRect();
+        const x = (e.clientX - rect.left) * width / rect.width;
+        const y = (e.clientY - rect.top) * height / rect.height;
+        image.orbit_zoom(-Math.sign(e.deltaY), x, y);
+    }, { passive: false });
+
+    // click selects the object under the cursor, shift + click selects the face
+    canvas.addEventListener('click', (e) => {
+        // the end of a drag is not a click
+        if (dragDistance > 4)
+            return;
+        const rect = canvas.getBoundingClientRect();
+        const x = Math.floor((e.clientX - rect.left) * width / rect.width);
+        const y = Math.floor((e.clientY - rect.top) * height / rect.height);
+        const pick = image.pick(x, y);
+
+        image.clear_selection();
+        if (pick) {
+            console.log(`object ${pick.object_handle}, face ${pick.face_index}, ` +
+                `position (${pick.x.toFixed(3)}, ${pick.y.toFixed(3)}, ${pick.z.toFixed(3)}), ` +
+                `uv (${pick.u.toFixed(3)}, ${pick.v.toFixed(3)}), depth ${pick.depth.toFixed(3)}`);
+            if (e.shiftKey) {
+                image.select_face(pick.object_handle, pick.face_index, true);
+            } else {
+                image.select_object(pick.object_handle, true);
+            }
+            pick.free();
+        }
+        image.compute();
+        render();
+    });
+
     document.addEventListener('keyup', (e) => {
         pressedKeys[e.code] = false;
         switch (e.code) {
@@ -252,6 +479,34 @@ let model_rotation = params.has('model-rotation') ? params.get('model-rotation')
             case "Space":
                 model_rotation = !model_rotation;
                 break;
+            case "Digit1": case "Digit2": case "Digit3":
+            case "Digit4": case "Digit5": case "Digit6":
+            case "Digit7": case "Digit8": case "Digit9": {
+                // shift + digit saves the view, digit flies back to it
+                const name = `view ${e.code.slice(-1)}`;
+                if (e.shiftKey) {
+                    image.save_camera_bookmark(name);
+                    console.log(`saved ${name}, views: ${image.get_camera_bookmark_names().join(', ')}`);
+                } else if (!image.restore_camera_bookmark(name, 1)) {
+                    console.log(`${name} is not saved`);
+                }
+                break;
+            }
+            case "KeyT": // turntable around the scene
+                if (turntable) {
+                    image.pause_camera_path();
+                    turntable = false;
+                } else {
+                    turntable = startTurntable();
+                }
+                break;
+            case "KeyF": // fit the scene into the view
+                image.frame_all(0.5);
+                break;
+            case "KeyP": // perspective - orthographic - isometric - dimetric
+                projection = (projection + 1) % 4;
+                image.set_camera_projection(projection);
+                break;
                 
         }
         image.compute()
@@ -0,0 +1,187 @@
+use crate::types::*;
+use crate::texture::{Texture, TextureWrap, ColorSpace};
+use crate::environment::{Environment, direction_to_uv};
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum BackgroundMode {
+    Solid,
+    // vertical gradient over the screen
+    Gradient,
+    Cubemap,
+    // equirectangular panorama
+    Panorama,
+    // the image based lighting environment
+    Environment
+}
+
+impl BackgroundMode {
+    pub fn from_id(mode_id: u32) -> Option<BackgroundMode> {
+        match mode_id {
+            1 => Some(BackgroundMode::Solid),
+            2 => Some(BackgroundMode::Gradient),
+            3 => Some(BackgroundMode::Cubemap),
+            4 => Some(BackgroundMode::Panorama),
+            5 => Some(BackgroundMode::Environment),
+            _ => None
+        }
+    }
+
+    pub fn is_directional(self) -> bool {
+        matches!(self, BackgroundMode::Cubemap | BackgroundMode::Panorama | BackgroundMode::Environment)
+    }
+}
+
+pub struct Background {
+    pub mode: BackgroundMode,
+    pub color: Color<f64>,
+    pub top_color: Color<f64>,
+    pub bottom_color: Color<f64>,
+    // +x, -x, +y, -y, +z, -z; srgb data written from js
+    pub cubemap_data: Vec<Texture<Pixel>>,
+    pub panorama_data: Texture<Pixel>,
+    // decoded linear colors
+    pub cubemap: Vec<Texture<Color<f64>>>,
+    pub panorama: Texture<Color<f64>>
+}
+
+fn decode_texture(data: &Texture<Pixel>, texture: &mut Texture<Color<f64>>) {
+    texture.set_size(data.width, data.height, Color { r: 0., g: 0., b: 0. });
+    for (color, pixel) in texture.data.iter_mut().zip(data.data.iter()) {
+        *color = ColorSpace::Srgb.decode_color(&pixel.color);
+    }
+    texture.generate_mips();
+}
+
+impl Background {
+    pub fn new() -> Background {
+        Background {
+            mode: BackgroundMode::Solid,
+            color: Color { r: 1., g: 1., b: 1. },
+            top_color: Color { r: 1., g: 1., b: 1. },
+            bottom_color: Color { r: 0.6, g: 0.6, b: 0.6 },
+            cubemap_data: (0..6).map(|_| Texture::new()).collect(),
+            panorama_data: Texture::new(),
+            cubemap: (0..6).map(|_| {
+                let mut face = Texture::new();
+                face.sampler.wrap_u = TextureWrap::ClampToEdge;
+                face.sampler.wrap_v = TextureWrap::ClampToEdge;
+                face
+            }).collect(),
+            panorama: {
+                let mut panorama = Texture::new();
+                panorama.sampler.wrap_u = TextureWrap::Repeat;
+                panorama.sampler.wrap_v = TextureWrap::ClampToEdge;
+                panorama
+            }
+        }
+    }
+
+    // texture_index: 1..6 - cubemap faces (+x, -x, +y, -y, +z, -z), 7 - panorama
+    fn get_texture(&mut self, texture_index: usize) -> Option<&mut Texture<Pixel>> {
+        match texture_index {
+            1..=6 => Some(&mut self.cubemap_data[texture_index - 1]),
+            7 => Some(&mut self.panorama_data),
+            _ => None
+        }
+    }
+
+    pub fn set_texture_size(&mut self, texture_index: usize, width: usize, height: usize) {
+        if let Some(texture) = self.get_texture(texture_index) {
+            texture.set_size(width, height, Pixel { color: black_color, a: 255 });
+        }
+    }
+
+    pub fn get_texture_pixels(&mut self, texture_index: usize) -> *const Pixel {
+        match self.get_texture(texture_index) {
+            Some(texture) => texture.get_data_pointer(),
+            None => std::ptr::null()
+        }
+    }
+
+    // the textures have to be written before the mode is set
+    pub fn set_mode(&mut self, mode: BackgroundMode) {
+        self.mode = mode;
+        match mode {
+            BackgroundMode::Cubemap => {
+                for (face, data) in self.cubemap.iter_mut().zip(self.cubemap_data.iter()) {
+                    decode_texture(data, face);
+                }
+            },
+            BackgroundMode::Panorama => decode_texture(&self.panorama_data, &mut self.panorama),
+            _ => {}
+        }
+    }
+
+    fn sample_cubemap(&self, direction: &Vector3<f64>, pixel_angle: f64) -> Color<f64> {
+        let (x, y, z) = (direction[0], direction[1], direction[2]);
+        let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+
+        // major axis selects the face, s and t go along the face image (t downwards)
+        let (face, s, t, major) = if ax >= ay && ax >= az {
+            if x > 0. { (0, -z, -y, ax) } else { (1, z, -y, ax) }
+        } else if ay >= az {
+            if y > 0. { (2, x, z, ay) } else { (3, x, -z, ay) }
+        } else if z > 0. {
+            (4, x, -y, az)
+        } else {
+            (5, -x, -y, az)
+        };
+
+        let texture = &self.cubemap[face];
+        let u = (s / major + 1.) / 2.;
+        let v = 1. - (t / major + 1.) / 2.;
+        // a face covers 90 degrees
+        let texels_per_pixel = pixel_angle * texture.width as f64 / (std::f64::consts::PI / 2.);
+        texture.sample(u, v, texels_per_pixel.max(1.).log2())
+    }
+
+    fn sample_panorama(&self, direction: &Vector3<f64>, pixel_angle: f64) -> Color<f64> {
+        let (u, v) = direction_to_uv(direction);
+        let texels_per_pixel = pixel_angle * self.panorama.width as f64 / (2. * std::f64::consts::PI);
+        self.panorama.sample(u, v, texels_per_pixel.max(1.).log2())
+    }
+
+    // y_ratio [0:1] goes from the top of the screen, direction is the view direction of the pixel,
+    // pixel_angle is the angle covered by one pixel used to choose the mip level
+    pub fn get_color(&self, y_ratio: f64, direction: &Vector3<f64>, pixel_angle: f64, environment: &Environment) -> Color<f64> {
+        match self.mode {
+            BackgroundMode::Solid => self.color,
+            BackgroundMode::Gradient => self.top_color * (1. - y_ratio) + self.bottom_color * y_ratio,
+            // the solid color until the images are uploaded
+            BackgroundMode::Cubemap => if self.cubemap.iter().any(|face| face.width == 0 || face.height == 0) {
+                self.color
+            } else {
+                self.sample_cubemap(direction, pixel_angle)
+            },
+            BackgroundMode::Panorama => if self.panorama.width == 0 || self.panorama.height == 0 {
+                self.color
+            } else {
+                self.sample_panorama(direction, pixel_angle)
+            },
+            BackgroundMode::Environment => {
+                if environment.enabled {
+                    environment.radiance(direction, 0.)
+                } else {
+                    self.color
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_not_uploaded_show_color() {
+        let mut background = Background::new();
+        background.color = Color { r: 0.1, g: 0.2, b: 0.3 };
+        let environment = Environment::new();
+        for mode in [BackgroundMode::Cubemap, BackgroundMode::Panorama] {
+            background.set_mode(mode);
+            let color = background.get_color(0.5, &Vector3::new(0.3, -0.2, 1.).normalize(), 0.01, &environment);
+            assert_eq!((color.r, color.g, color.b), (0.1, 0.2, 0.3));
+        }
+    }
+}
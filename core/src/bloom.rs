@@ -0,0 +1,210 @@
+use crate::types::*;
+use crate::console::log;
+
+// the pyramid stops at this amount of levels or when a level gets smaller than 2 pixels
+const MAX_LEVELS: usize = 6;
+// width of the soft transition around the threshold, relative to the threshold;
+// with the default threshold it starts above 1, so the plain white does not bloom
+const SOFT_KNEE: f64 = 0.2;
+
+struct Level {
+    width: usize,
+    height: usize,
+    data: Vec<Color<f32>>
+}
+
+impl Level {
+    fn new(width: usize, height: usize) -> Level {
+        Level {
+            width,
+            height,
+            data: vec![Color { r: 0., g: 0., b: 0. }; width * height]
+        }
+    }
+
+    // bilinear sample in the texel coordinates of the level (texel centers at +0.5), clamped to the edge
+    fn sample(&self, x: f64, y: f64) -> Color<f64> {
+        let x = (x - 0.5).clamp(0., (self.width - 1) as f64);
+        let y = (y - 0.5).clamp(0., (self.height - 1) as f64);
+        let x0 = x.floor() as usize;
+        let y0 = y.floor() as usize;
+        let x1 = (x0 + 1).min(self.width - 1);
+        let y1 = (y0 + 1).min(self.height - 1);
+        let tx = x - x0 as f64;
+        let ty = y - y0 as f64;
+
+        let texel = |x: usize, y: usize| self.data[y * self.width + x].to_f64();
+        (texel(x0, y0) * (1. - tx) + texel(x1, y0) * tx) * (1. - ty) +
+        (texel(x0, y1) * (1. - tx) + texel(x1, y1) * tx) * ty
+    }
+}
+
+pub struct Bloom {
+    pub enabled: bool,
+    // luminance above which the hdr color starts to bloom
+    pub threshold: f64,
+    // spread of the upsampling filter in texels of each level
+    pub radius: f64,
+    pub intensity: f64,
+    // thresholded image at the full resolution, kept between the frames with the levels
+    bright: Level,
+    // the level 0 is the half of the image resolution
+    levels: Vec<Level>
+}
+
+impl Bloom {
+    pub fn new() -> Bloom {
+        Bloom {
+            enabled: true,
+            threshold: 1.25,
+            radius: 1.,
+            intensity: 1.,
+            bright: Level::new(0, 0),
+            levels: Vec::new()
+        }
+    }
+
+    pub fn set_param(&mut self, param_id: u32, param_value: f64) {
+        match param_id {
+            1 => self.enabled = param_value != 0.,
+            2 => self.threshold = param_value.max(0.),
+            3 => self.radius = param_value.max(0.),
+            4 => self.intensity = param_value.max(0.),
+            _ => log("Not recognized bloom param")
+        }
+    }
+
+    fn resize(&mut self, width: usize, height: usize) {
+        if self.bright.width == width && self.bright.height == height {
+            return;
+        }
+
+        self.bright = Level::new(width, height);
+        self.levels.clear();
+        let (mut level_width, mut level_height) = (width / 2, height / 2);
+        while self.levels.len() < MAX_LEVELS && level_width >= 2 && level_height >= 2 {
+            self.levels.push(Level::new(level_width, level_height));
+            level_width /= 2;
+            level_height /= 2;
+        }
+    }
+
+    // part of the color above the threshold, with the quadratic soft knee
+    fn prefilter(&self, color: Color<f64>) -> Color<f64> {
+        let brightness = color.r.max(color.g).max(color.b);
+        let knee = self.threshold * SOFT_KNEE;
+        let soft = (brightness - self.threshold + knee).clamp(0., 2. * knee);
+        let soft = soft * soft / (4. * knee + 1e-5);
+        let contribution = soft.max(brightness - self.threshold) / brightness.max(1e-5);
+        color * contribution
+    }
+
+    // the four bilinear taps around the center cover the 4x4 texels of the source
+    fn downsample(source: &Level, x: usize, y: usize) -> Color<f64> {
+        let (sx, sy) = ((x as f64 + 0.5) * 2., (y as f64 + 0.5) * 2.);
+        (
+            source.sample(sx, sy) * 4. +
+            source.sample(sx - 1., sy - 1.) + source.sample(sx + 1., sy - 1.) +
+            source.sample(sx - 1., sy + 1.) + source.sample(sx + 1., sy + 1.)
+        ) * (1. / 8.)
+    }
+
+    // 3x3 tent filter over the smaller level, position is in the texels of the bigger one
+    fn upsample(&self, source: &Level, x: f64, y: f64) -> Color<f64> {
+        let (sx, sy) = (x / 2., y / 2.);
+        let r = self.radius;
+        (
+            source.sample(sx - r, sy - r) + source.sample(sx, sy - r) * 2. + source.sample(sx + r, sy - r) +
+            source.sample(sx - r, sy) * 2. + source.sample(sx, sy) * 4. + source.sample(sx + r, sy) * 2. +
+            source.sample(sx - r, sy + r) + source.sample(sx, sy + r) * 2. + source.sample(sx + r, sy + r)
+        ) * (1. / 16.)
+    }
+
+    // adds the blurred bright parts of the hdr buffer to itself
+    pub fn apply(&mut self, color_buf: &mut [Color<f32>], width: usize, height: usize) {
+        if !self.enabled || self.intensity == 0. {
+            return;
+        }
+        self.resize(width, height);
+        if self.levels.is_empty() {
+            return;
+        }
+
+        // nothing reaching the soft knee, nothing to blur
+        let knee_start = self.threshold * (1. - SOFT_KNEE);
+        if !color_buf.iter().any(|color| color.r.max(color.g).max(color.b) as f64 > knee_start) {
+            return;
+        }
+
+        let mut bright = std::mem::replace(&mut self.bright, Level::new(0, 0));
+        for (target, color) in bright.data.iter_mut().zip(color_buf.iter()) {
+            *target = self.prefilter(color.to_f64()).to_f32();
+        }
+        let first = &mut self.levels[0];
+        for y in 0..first.height {
+            for x in 0..first.width {
+                first.data[y * first.width + x] = Self::downsample(&bright, x, y).to_f32();
+            }
+        }
+        self.bright = bright;
+
+        for level in 1..self.levels.len() {
+            let (bigger, smaller) = self.levels.split_at_mut(level);
+            let (source, target) = (&bigger[level - 1], &mut smaller[0]);
+            for y in 0..target.height {
+                for x in 0..target.width {
+                    target.data[y * target.width + x] = Self::downsample(source, x, y).to_f32();
+                }
+            }
+        }
+
+        // each level accumulates the upsampled blur of the smaller ones
+        for level in (1..self.levels.len()).rev() {
+            let mut target = std::mem::replace(&mut self.levels[level - 1], Level::new(0, 0));
+            for y in 0..target.height {
+                for x in 0..target.width {
+                    let index = y * target.width + x;
+                    let blur = self.upsample(&self.levels[level], x as f64 + 0.5, y as f64 + 0.5);
+                    target.data[index] = (target.data[index].to_f64() + blur).to_f32();
+                }
+            }
+            self.levels[level - 1] = target;
+        }
+
+        // every level carries the whole energy, so the sum is averaged
+        let scale = self.intensity / self.levels.len() as f64;
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                let blur = self.upsample(&self.levels[0], x as f64 + 0.5, y as f64 + 0.5);
+                color_buf[index] = (color_buf[index].to_f64() + blur * scale).to_f32();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn white_does_not_bloom() {
+        let mut bloom = Bloom::new();
+        let mut color_buf = vec![Color { r: 1., g: 1., b: 1. }; 32 * 16];
+        bloom.apply(&mut color_buf, 32, 16);
+        assert!(color_buf.iter().all(|color| (color.r, color.g, color.b) == (1., 1., 1.)));
+    }
+
+    #[test]
+    fn bright_pixel_spreads() {
+        let mut bloom = Bloom::new();
+        let (width, height) = (32, 16);
+        // the second frame reuses the buffers
+        for _ in 0..2 {
+            let mut color_buf = vec![Color { r: 0., g: 0., b: 0. }; width * height];
+            color_buf[8 * width + 16] = Color { r: 50., g: 50., b: 50. };
+            bloom.apply(&mut color_buf, width, height);
+            assert!(color_buf[8 * width + 18].r > 0.);
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::types::*;
+
+// world space bounds for the js side
+#[wasm_bindgen]
+#[derive(Copy, Clone)]
+pub struct BoundingBox {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub min_z: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+    pub max_z: f64
+}
+
+// axis aligned bounding box, empty when min is greater than max
+#[derive(Copy, Clone)]
+pub struct Aabb {
+    pub min: Vector3<f64>,
+    pub max: Vector3<f64>
+}
+
+impl Aabb {
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vector3::repeat(f64::MAX),
+            max: Vector3::repeat(f64::MIN)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min[0] > self.max[0]
+    }
+
+    pub fn grow(&mut self, point: &Vector3<f64>) {
+        self.min = self.min.inf(point);
+        self.max = self.max.sup(point);
+    }
+
+    pub fn merge(&mut self, other: &Aabb) {
+        self.min = self.min.inf(&other.min);
+        self.max = self.max.sup(&other.max);
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.;
+        }
+        let size = self.max - self.min;
+        2. * (size[0] * size[1] + size[1] * size[2] + size[2] * size[0])
+    }
+
+    pub fn to_bounding_box(self) -> Option<BoundingBox> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(BoundingBox {
+            min_x: self.min[0],
+            min_y: self.min[1],
+            min_z: self.min[2],
+            max_x: self.max[0],
+            max_y: self.max[1],
+            max_z: self.max[2]
+        })
+    }
+
+    pub fn corners(&self) -> [Vector3<f64>; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Vector3::new(min[0], min[1], min[2]),
+            Vector3::new(max[0], min[1], min[2]),
+            Vector3::new(min[0], max[1], min[2]),
+            Vector3::new(max[0], max[1], min[2]),
+            Vector3::new(min[0], min[1], max[2]),
+            Vector3::new(max[0], min[1], max[2]),
+            Vector3::new(min[0], max[1], max[2]),
+            Vector3::new(max[0], max[1], max[2])
+        ]
+    }
+
+    // the box around the transformed corners
+    pub fn transform(&self, matrix: &Matrix4<f64>) -> Aabb {
+        let mut bounds = Aabb::empty();
+        if self.is_empty() {
+            return bounds;
+        }
+        for corner in self.corners().iter() {
+            bounds.grow(&(matrix * Point3::from(*corner).to_homogeneous()).xyz());
+        }
+        bounds
+    }
+
+    // distance to the entry point (slab test), None when the ray misses the box or it is farther than max_distance
+    pub fn intersect(&self, origin: &Vector3<f64>, inverse_direction: &Vector3<f64>, max_distance: f64) -> Option<f64> {
+        let mut near = 0.;
+        let mut far = max_distance;
+        for axis in 0..3 {
+            let t1 = (self.min[axis] - origin[axis]) * inverse_direction[axis];
+            let t2 = (self.max[axis] - origin[axis]) * inverse_direction[axis];
+            near = t1.min(t2).max(near);
+            far = t1.max(t2).min(far);
+        }
+        if near <= far { Some(near) } else { None }
+    }
+}
@@ -0,0 +1,83 @@
+use std::f64::consts::PI;
+
+use crate::types::*;
+
+// GGX / Trowbridge-Reitz normal distribution
+pub fn distribution_ggx(n_dot_h: f64, roughness: f64) -> f64 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let denom = n_dot_h * n_dot_h * (a2 - 1.) + 1.;
+    a2 / (PI * denom * denom).max(1e-12)
+}
+
+fn geometry_schlick_ggx(n_dot_x: f64, roughness: f64) -> f64 {
+    let r = roughness + 1.;
+    let k = r * r / 8.;
+    n_dot_x / (n_dot_x * (1. - k) + k)
+}
+
+// Smith geometry term, shadowing from both the view and the light directions
+pub fn geometry_smith(n_dot_v: f64, n_dot_l: f64, roughness: f64) -> f64 {
+    geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness)
+}
+
+pub fn fresnel_schlick(cos_theta: f64, f0: &Color<f64>) -> Color<f64> {
+    let k = (1. - cos_theta).clamp(0., 1.).powi(5);
+    *f0 + (Color { r: 1., g: 1., b: 1. } - *f0) * k
+}
+
+// fresnel averaged over the rough surface, used for the ambient light
+pub fn fresnel_schlick_roughness(cos_theta: f64, f0: &Color<f64>, roughness: f64) -> Color<f64> {
+    let k = (1. - cos_theta).clamp(0., 1.).powi(5);
+    let max_reflectance = Color {
+        r: (1. - roughness).max(f0.r),
+        g: (1. - roughness).max(f0.g),
+        b: (1. - roughness).max(f0.b)
+    };
+    *f0 + (max_reflectance - *f0) * k
+}
+
+// analytic approximation of the split-sum environment brdf (Karis, "Physically Based Shading on Mobile")
+pub fn environment_brdf(f0: &Color<f64>, roughness: f64, n_dot_v: f64) -> Color<f64> {
+    let r0 = 1. - roughness;
+    let r1 = roughness * -0.0275 + 0.0425;
+    let r2 = roughness * -0.572 + 1.04;
+    let r3 = roughness * 0.022 - 0.04;
+    let a004 = (r0 * r0).min(2f64.powf(-9.28 * n_dot_v)) * r0 + r1;
+    let scale = a004 * -1.04 + r2;
+    let bias = a004 * 1.04 + r3;
+    *f0 * scale + Color { r: bias, g: bias, b: bias }
+}
+
+// reflectance at normal incidence: 4% for dielectrics, albedo for metals
+pub fn base_reflectance(albedo: &Color<f64>, metallic: f64) -> Color<f64> {
+    Color { r: 0.04, g: 0.04, b: 0.04 } * (1. - metallic) + *albedo * metallic
+}
+
+// outgoing light for the unit light radiance, all the vectors are normalized and point away from the surface;
+// the result is multiplied by PI so the white lambertian surface lit head-on by the light of intensity 1 is white
+pub fn cook_torrance(
+    normal: &Vector3<f64>, view_direction: &Vector3<f64>, light_direction: &Vector3<f64>,
+    albedo: &Color<f64>, metallic: f64, roughness: f64
+) -> Color<f64> {
+    let n_dot_l = normal.dot(light_direction);
+    if n_dot_l <= 0. {
+        return Color { r: 0., g: 0., b: 0. };
+    }
+    let n_dot_v = normal.dot(view_direction).max(1e-4);
+    let half = (view_direction + light_direction).normalize();
+    let n_dot_h = normal.dot(&half).max(0.);
+    let h_dot_v = half.dot(view_direction).max(0.);
+    let roughness = roughness.clamp(0.04, 1.);
+
+    let fresnel = fresnel_schlick(h_dot_v, &base_reflectance(albedo, metallic));
+    let distribution = distribution_ggx(n_dot_h, roughness);
+    let geometry = geometry_smith(n_dot_v, n_dot_l, roughness);
+    let specular = fresnel * (distribution * geometry / (4. * n_dot_v * n_dot_l));
+
+    // energy which is not reflected is refracted, metals do not have the diffuse part
+    let diffuse_part = (Color { r: 1., g: 1., b: 1. } - fresnel) * (1. - metallic);
+    let diffuse = diffuse_part * *albedo * (1. / PI);
+
+    (diffuse + specular) * (n_dot_l * PI)
+}
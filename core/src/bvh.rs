@@ -0,0 +1,314 @@
+use crate::types::*;
+use crate::bounds::Aabb;
+
+const BIN_COUNT: usize = 12;
+const MAX_LEAF_FACES: usize = 4;
+// cost of visiting a node relative to intersecting a triangle
+const TRAVERSAL_COST: f64 = 1.;
+
+struct Node {
+    bounds: Aabb,
+    // inner node: index of the left child (the right one follows it); leaf: first index into face_indexes
+    first: usize,
+    // 0 for the inner nodes
+    count: usize
+}
+
+pub struct Hit {
+    pub face_index: usize,
+    // in the units of the ray direction
+    pub distance: f64,
+    pub barycentric: Point3<f64>
+}
+
+// bounding volume hierarchy over the faces of one object, in the object space
+pub struct Bvh {
+    nodes: Vec<Node>,
+    face_indexes: Vec<usize>
+}
+
+fn face_points(vertices: &[Vertex], face: &Face) -> [Vector3<f64>; 3] {
+    [
+        vertices[face.vertices_indexes[0]].xyz(),
+        vertices[face.vertices_indexes[1]].xyz(),
+        vertices[face.vertices_indexes[2]].xyz()
+    ]
+}
+
+// Moller-Trumbore, returns the distance and the weights of the second and the third vertices
+fn intersect_triangle(origin: &Vector3<f64>, direction: &Vector3<f64>, points: &[Vector3<f64>; 3]) -> Option<(f64, f64, f64)> {
+    let edge1 = points[1] - points[0];
+    let edge2 = points[2] - points[0];
+    let p = direction.cross(&edge2);
+    let determinant = edge1.dot(&p);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    let inverse_determinant = 1. / determinant;
+    let to_origin = origin - points[0];
+    let u = to_origin.dot(&p) * inverse_determinant;
+    if !(0. ..=1.).contains(&u) {
+        return None;
+    }
+    let q = to_origin.cross(&edge1);
+    let v = direction.dot(&q) * inverse_determinant;
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+    let distance = edge2.dot(&q) * inverse_determinant;
+    if distance <= 0. {
+        return None;
+    }
+    Some((distance, u, v))
+}
+
+impl Bvh {
+    pub fn build(vertices: &[Vertex], faces: &[Face]) -> Bvh {
+        let mut face_bounds = Vec::with_capacity(faces.len());
+        let mut centroids = Vec::with_capacity(faces.len());
+        for face in faces.iter() {
+            let mut bounds = Aabb::empty();
+            let points = face_points(vertices, face);
+            for point in points.iter() {
+                bounds.grow(point);
+            }
+            face_bounds.push(bounds);
+            centroids.push((points[0] + points[1] + points[2]) / 3.);
+        }
+
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(faces.len() * 2),
+            face_indexes: (0..faces.len()).collect()
+        };
+        let mut root_bounds = Aabb::empty();
+        for bounds in face_bounds.iter() {
+            root_bounds.merge(bounds);
+        }
+        bvh.nodes.push(Node { bounds: root_bounds, first: 0, count: faces.len() });
+        bvh.subdivide(0, &face_bounds, &centroids);
+        bvh
+    }
+
+    // binned surface area heuristic split along the best axis
+    fn subdivide(&mut self, node_index: usize, face_bounds: &[Aabb], centroids: &[Vector3<f64>]) {
+        let (first, count) = (self.nodes[node_index].first, self.nodes[node_index].count);
+        if count <= MAX_LEAF_FACES {
+            return;
+        }
+
+        let mut centroid_bounds = Aabb::empty();
+        for face_index in self.face_indexes[first..first + count].iter() {
+            centroid_bounds.grow(&centroids[*face_index]);
+        }
+
+        let mut best: Option<(usize, f64, f64)> = None;
+        for axis in 0..3 {
+            let (low, high) = (centroid_bounds.min[axis], centroid_bounds.max[axis]);
+            if high - low < 1e-12 {
+                continue;
+            }
+            let scale = BIN_COUNT as f64 / (high - low);
+            let bin_of = |centroid: &Vector3<f64>| (((centroid[axis] - low) * scale) as usize).min(BIN_COUNT - 1);
+
+            let mut bins = [(Aabb::empty(), 0usize); BIN_COUNT];
+            for face_index in self.face_indexes[first..first + count].iter() {
+                let bin = &mut bins[bin_of(&centroids[*face_index])];
+                bin.0.merge(&face_bounds[*face_index]);
+                bin.1 += 1;
+            }
+
+            // costs of the splits after every bin, accumulated from both sides
+            let mut left_area = [0.; BIN_COUNT - 1];
+            let mut left_count = [0; BIN_COUNT - 1];
+            let (mut bounds, mut faces) = (Aabb::empty(), 0);
+            for i in 0..BIN_COUNT - 1 {
+                bounds.merge(&bins[i].0);
+                faces += bins[i].1;
+                left_area[i] = bounds.surface_area();
+                left_count[i] = faces;
+            }
+            let (mut bounds, mut faces) = (Aabb::empty(), 0);
+            for i in (1..BIN_COUNT).rev() {
+                bounds.merge(&bins[i].0);
+                faces += bins[i].1;
+                let cost = left_area[i - 1] * left_count[i - 1] as f64 + bounds.surface_area() * faces as f64;
+                if left_count[i - 1] > 0 && faces > 0 && best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, low + i as f64 / scale, cost));
+                }
+            }
+        }
+
+        let (axis, split, cost) = match best {
+            Some(best) => best,
+            None => return
+        };
+        let leaf_cost = count as f64 * self.nodes[node_index].bounds.surface_area();
+        if cost + TRAVERSAL_COST * self.nodes[node_index].bounds.surface_area() >= leaf_cost {
+            return;
+        }
+
+        // partition the faces around the split plane
+        let mut i = first;
+        let mut j = first + count;
+        while i < j {
+            if centroids[self.face_indexes[i]][axis] < split {
+                i += 1;
+            } else {
+                j -= 1;
+                self.face_indexes.swap(i, j);
+            }
+        }
+        let left_count = i - first;
+        if left_count == 0 || left_count == count {
+            return;
+        }
+
+        let left_index = self.nodes.len();
+        for (child_first, child_count) in [(first, left_count), (i, count - left_count)] {
+            let mut bounds = Aabb::empty();
+            for face_index in self.face_indexes[child_first..child_first + child_count].iter() {
+                bounds.merge(&face_bounds[*face_index]);
+            }
+            self.nodes.push(Node { bounds, first: child_first, count: child_count });
+        }
+        self.nodes[node_index].first = left_index;
+        self.nodes[node_index].count = 0;
+
+        self.subdivide(left_index, face_bounds, centroids);
+        self.subdivide(left_index + 1, face_bounds, centroids);
+    }
+
+    // nearest hit of the ray, both are in the object space
+    pub fn raycast(&self, vertices: &[Vertex], faces: &[Face], origin: &Vector3<f64>, direction: &Vector3<f64>) -> Option<Hit> {
+        if self.face_indexes.is_empty() {
+            return None;
+        }
+        let inverse_direction = direction.map(|d| 1. / d);
+        let mut nearest: Option<Hit> = None;
+        let mut max_distance = f64::MAX;
+
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if node.bounds.intersect(origin, &inverse_direction, max_distance).is_none() {
+                continue;
+            }
+
+            if node.count > 0 {
+                for face_index in self.face_indexes[node.first..node.first + node.count].iter() {
+                    let points = face_points(vertices, &faces[*face_index]);
+                    if let Some((distance, u, v)) = intersect_triangle(origin, direction, &points) {
+                        if distance < max_distance {
+                            max_distance = distance;
+                            nearest = Some(Hit {
+                                face_index: *face_index,
+                                distance,
+                                barycentric: Point3::new(1. - u - v, u, v)
+                            });
+                        }
+                    }
+                }
+                continue;
+            }
+
+            // the nearer child is visited first
+            let (left, right) = (node.first, node.first + 1);
+            let left_distance = self.nodes[left].bounds.intersect(origin, &inverse_direction, max_distance);
+            let right_distance = self.nodes[right].bounds.intersect(origin, &inverse_direction, max_distance);
+            match (left_distance, right_distance) {
+                (Some(l), Some(r)) => {
+                    if l < r {
+                        stack.push(right);
+                        stack.push(left);
+                    } else {
+                        stack.push(left);
+                        stack.push(right);
+                    }
+                },
+                (Some(_), None) => stack.push(left),
+                (None, Some(_)) => stack.push(right),
+                (None, None) => {}
+            }
+        }
+        nearest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // xorshift, the same numbers on every run
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> f64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64 * 2. - 1.
+        }
+
+        fn vector(&mut self) -> Vector3<f64> {
+            Vector3::new(self.next(), self.next(), self.next())
+        }
+    }
+
+    // small triangles scattered in the cube -10..10
+    fn soup(random: &mut Random, count: usize) -> (Vec<Vertex>, Vec<Face>) {
+        let mut vertices = Vec::new();
+        let mut faces = Vec::new();
+        for i in 0..count {
+            let center = random.vector() * 10.;
+            for _ in 0..3 {
+                vertices.push((center + random.vector()).to_homogeneous() + Vector4::w());
+            }
+            faces.push(Face {
+                vertices_indexes: Vector3::new(i * 3, i * 3 + 1, i * 3 + 2),
+                texture_vertices_indexes: Vector3::zeros(),
+                vertices_normals_indexes: Vector3::zeros(),
+                normal: Vector4::zeros()
+            });
+        }
+        (vertices, faces)
+    }
+
+    fn brute_force(vertices: &[Vertex], faces: &[Face], origin: &Vector3<f64>, direction: &Vector3<f64>) -> Option<(usize, f64)> {
+        faces.iter().enumerate()
+            .filter_map(|(face_index, face)| {
+                let (distance, _, _) = intersect_triangle(origin, direction, &face_points(vertices, face))?;
+                Some((face_index, distance))
+            })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+    }
+
+    #[test]
+    fn raycast_matches_brute_force() {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        let (vertices, faces) = soup(&mut random, 2000);
+        let bvh = Bvh::build(&vertices, &faces);
+
+        let mut hits = 0;
+        for i in 0..500 {
+            let origin = random.vector() * 15.;
+            let mut direction = random.vector();
+            // some rays along the axes, their inverse direction is infinite
+            if i % 5 == 0 {
+                direction = Vector3::zeros();
+                direction[i / 5 % 3] = if i % 2 == 0 { 1. } else { -1. };
+            }
+            let expected = brute_force(&vertices, &faces, &origin, &direction);
+            let hit = bvh.raycast(&vertices, &faces, &origin, &direction).map(|hit| (hit.face_index, hit.distance));
+            assert_eq!(hit, expected);
+            hits += hit.is_some() as usize;
+        }
+        // enough rays have to hit something, or the comparison shows little
+        assert!(hits > 100);
+    }
+
+    #[test]
+    fn empty_mesh_has_no_hit() {
+        let bvh = Bvh::build(&[], &[]);
+        assert!(bvh.raycast(&[], &[], &Vector3::zeros(), &Vector3::x()).is_none());
+    }
+}
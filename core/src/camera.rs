@@ -1,5 +1,92 @@
+use std::f64::consts::{PI, FRAC_PI_2, FRAC_PI_4};
+
 use crate::types::*;
 use crate::console::log;
+use crate::bounds::Aabb;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Projection {
+    Perspective,
+    Orthographic,
+    // orthographic views from the fixed angles, the camera is turned around the pivot
+    Isometric,
+    // 2:1 pixel art style
+    Dimetric
+}
+
+impl Projection {
+    pub fn from_id(projection_id: u32) -> Option<Projection> {
+        match projection_id {
+            0 => Some(Projection::Perspective),
+            1 => Some(Projection::Orthographic),
+            2 => Some(Projection::Isometric),
+            3 => Some(Projection::Dimetric),
+            _ => None
+        }
+    }
+
+    pub fn is_orthographic(self) -> bool {
+        self != Projection::Perspective
+    }
+
+    // elevation of the camera above the horizon for the axonometric presets
+    fn preset_elevation(self) -> Option<f64> {
+        match self {
+            Projection::Isometric => Some(f64::atan(1. / f64::sqrt(2.))),
+            Projection::Dimetric => Some(f64::asin(0.5)),
+            _ => None
+        }
+    }
+}
+
+// what the bookmarks keep and the transitions move the camera between
+#[derive(Copy, Clone)]
+pub struct CameraState {
+    pub position: Point3<f64>,
+    pub front: Vector3<f64>,
+    pub world_up: Vector3<f64>,
+    pub fov: f64,
+    pub projection: Projection,
+    pub pivot_distance: f64,
+    pub zoom: f64
+}
+
+// along the great circle, the opposite directions are turned around the axis
+pub fn slerp_direction(from: &Vector3<f64>, to: &Vector3<f64>, axis: &Vector3<f64>, t: f64) -> Vector3<f64> {
+    let (from, to) = (Unit::new_normalize(*from), Unit::new_normalize(*to));
+    let cos = from.dot(&to);
+    if cos >= 1. {
+        return from.into_inner();
+    }
+    match if cos > -1. + 1e-9 { from.try_slerp(&to, t, 1e-9) } else { None } {
+        Some(direction) => direction.into_inner(),
+        None => {
+            let axis = match (axis - from.as_ref() * axis.dot(&from)).try_normalize(1e-9) {
+                Some(axis) => axis,
+                None => from.cross(&Vector3::x()).try_normalize(1e-9).unwrap_or_else(Vector3::y)
+            };
+            UnitQuaternion::from_axis_angle(&Unit::new_unchecked(axis), PI * t) * from.into_inner()
+        }
+    }
+}
+
+impl CameraState {
+    // position and lens are interpolated linearly, the directions along the sphere;
+    // the projection switches halfway, the view around the pivot keeps its size so the switch is barely visible
+    pub fn interpolate(&self, other: &CameraState, t: f64) -> CameraState {
+        let world_up = slerp_direction(&self.world_up, &other.world_up, &self.front, t);
+        CameraState {
+            position: self.position + (other.position - self.position) * t,
+            front: slerp_direction(&self.front, &other.front, &world_up, t),
+            world_up,
+            fov: self.fov + (other.fov - self.fov) * t,
+            projection: if t < 0.5 { self.projection } else { other.projection },
+            pivot_distance: self.pivot_distance + (other.pivot_distance - self.pivot_distance) * t,
+            // the view size changes evenly
+            zoom: (self.zoom.ln() + (other.zoom.ln() - self.zoom.ln()) * t).exp()
+        }
+    }
+}
 
 pub struct Camera {
     pub fov: f64,
@@ -9,12 +96,48 @@ pub struct Camera {
     pub front: Vector3<f64>,
     pub look_at_matrix: Matrix4<f64>,
     pub projection_matrix: Matrix4<f64>,
+    pub projection: Projection,
+    // the orthographic view shows what the perspective one shows at the pivot distance, divided by the zoom
+    pub zoom: f64,
+    // distance in front of the camera to the point the views are framed around
+    pub pivot_distance: f64,
+    z_near: f64,
+    z_far: f64,
+    // the clipping planes are fitted to the scene bounds every frame
+    pub auto_clip_planes: bool,
+    // thin lens used by the depth of field: distance to the sharp plane in world units,
+    // aperture as the f-number and focal length in millimeters
+    pub focus_distance: f64,
+    pub aperture: f64,
+    pub focal_length: f64,
+    // focus on the object under the screen center every frame
+    pub autofocus: bool,
+    // how fast (1 / seconds) the velocities reach the requested ones, and fall to zero when nothing is requested
+    pub acceleration: f64,
+    pub damping: f64,
+    // requested velocities: units per second on the camera axes, radians per second
     speed: Vector3<f64>,
-    rotation: Vector3<f64> // pitch, yaw, scroll
+    rotation: Vector3<f64>, // pitch, yaw, scroll
+    velocity: Vector3<f64>,
+    angular_velocity: Vector3<f64>
+}
+
+// how far the velocity approaching the requested one at the rate takes the camera in dt,
+// integrated exactly, so the path does not depend on how dt is split; returns the distance and the new velocity
+fn approach(velocity: &Vector3<f64>, requested: &Vector3<f64>, rate: f64, dt: f64) -> (Vector3<f64>, Vector3<f64>) {
+    if rate.is_infinite() || rate * dt > 50. {
+        return (requested * dt, *requested);
+    }
+    if rate <= 0. {
+        return (velocity * dt, *velocity);
+    }
+    let decay = (-rate * dt).exp();
+    let difference = velocity - requested;
+    (requested * dt + difference * ((1. - decay) / rate), requested + difference * decay)
 }
 
 impl Camera {
-    fn compute_look_at(&position: &Point3<f64>, &target: &Point3<f64>, &world_up: &Vector3<f64>) -> Matrix4<f64>{
+    pub fn compute_look_at(&position: &Point3<f64>, &target: &Point3<f64>, &world_up: &Vector3<f64>) -> Matrix4<f64>{
         let direction = (position - target).normalize();
         let right = world_up.cross(&direction).normalize();
         let up = direction.cross(&right);
@@ -39,21 +162,10 @@ impl Camera {
     }
 
     fn compute_projection_matrix(fov: f64, aspect_ration: f64, z_near: f64, z_far: f64) -> Matrix4<f64> {
-        let w = 0.1;
-        let h = w / aspect_ration;
-
         let n = z_near;
         let f = z_far;
         let a = aspect_ration;
 
-        // ortographic projection
-        // Matrix4::new(
-        //     2. / w, 0.,          0.,                    0.,
-        //     0.,         2. / h, 0.,                    0.,
-        //     0.,         0.,          1. / (n - f), n / (n - f),
-        //     0.,         0.,          0.,                    1.
-        // )
-
         // perspective projection
         // Matrix4::new(
         //     2. * n / w, 0.,          0.,          0.,
@@ -71,6 +183,160 @@ impl Camera {
         )
     }
 
+    // h is the height of the view in the world units
+    fn compute_orthographic_matrix(h: f64, aspect_ration: f64, z_near: f64, z_far: f64) -> Matrix4<f64> {
+        let w = h * aspect_ration;
+
+        let n = z_near;
+        let f = z_far;
+
+        Matrix4::new(
+            2. / w, 0.,     0.,           0.,
+            0.,     2. / h, 0.,           0.,
+            0.,     0.,     1. / (n - f), n / (n - f),
+            0.,     0.,     0.,           1.
+        )
+    }
+
+    // height of the orthographic view in the world units
+    pub fn orthographic_height(&self) -> f64 {
+        2. * self.pivot_distance * f64::tan(self.fov / 2.) / self.zoom
+    }
+
+    fn update_projection(&mut self) {
+        self.projection_matrix = if self.projection.is_orthographic() {
+            Camera::compute_orthographic_matrix(self.orthographic_height(), self.aspect, self.z_near, self.z_far)
+        } else {
+            Camera::compute_projection_matrix(self.fov, self.aspect, self.z_near, self.z_far)
+        };
+    }
+
+    // the pivot stays in place, so the framing is kept; the presets also turn the camera around it
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+        if let Some(elevation) = projection.preset_elevation() {
+            let pivot = self.pivot();
+            // the nearest of the four diagonal directions to the current one
+            let yaw = (f64::atan2(self.front[0], self.front[2]) / FRAC_PI_2 - 0.5).round() * FRAC_PI_2 + FRAC_PI_4;
+            self.front = Vector3::new(
+                yaw.sin() * elevation.cos(),
+                -elevation.sin(),
+                yaw.cos() * elevation.cos()
+            );
+            self.position = pivot - self.front * self.pivot_distance;
+            self.update_look_at();
+        }
+        self.update_projection();
+    }
+
+    // near and far planes around the bounds seen through the look at matrix, None when they are behind
+    fn clip_planes_around(bounds: &Aabb, look_at: &Matrix4<f64>) -> Option<(f64, f64)> {
+        if bounds.is_empty() {
+            return None;
+        }
+        let view_bounds = bounds.transform(look_at);
+        // the camera looks along -z
+        let (nearest, farthest) = (-view_bounds.max[2], -view_bounds.min[2]);
+        if farthest <= 0. {
+            return None;
+        }
+        let margin = (farthest - nearest) * 0.01 + 1e-3;
+        let z_far = farthest + margin;
+        Some(((nearest - margin).max(z_far * 1e-4), z_far))
+    }
+
+    // the tightest planes around the bounds give the best depth precision
+    pub fn fit_clip_planes(&mut self, bounds: &Aabb) {
+        if let Some((z_near, z_far)) = Camera::clip_planes_around(bounds, &self.look_at_matrix) {
+            self.z_near = z_near;
+            self.z_far = z_far;
+            self.update_projection();
+        }
+    }
+
+    // moves the planes apart until the bounds seen from the state fit between them, never closer together
+    pub fn widen_clip_planes(&mut self, bounds: &Aabb, state: &CameraState) {
+        let look_at = Camera::compute_look_at(&state.position, &(state.position + state.front), &self.world_up);
+        if let Some((z_near, z_far)) = Camera::clip_planes_around(bounds, &look_at) {
+            self.z_near = self.z_near.min(z_near);
+            self.z_far = self.z_far.max(z_far);
+            self.update_projection();
+        }
+    }
+
+    // places the camera for the controllers, front has to be normalized
+    pub fn set_pose(&mut self, position: &Point3<f64>, front: &Vector3<f64>, pivot_distance: f64) {
+        self.position = *position;
+        self.front = *front;
+        self.update_look_at();
+        if self.pivot_distance != pivot_distance {
+            self.pivot_distance = pivot_distance;
+            self.update_projection();
+        }
+    }
+
+    pub fn state(&self) -> CameraState {
+        CameraState {
+            position: self.position,
+            front: self.front,
+            world_up: self.world_up,
+            fov: self.fov,
+            projection: self.projection,
+            pivot_distance: self.pivot_distance,
+            zoom: self.zoom
+        }
+    }
+
+    pub fn set_state(&mut self, state: &CameraState) {
+        self.position = state.position;
+        self.front = state.front.normalize();
+        self.world_up = state.world_up.normalize();
+        self.fov = state.fov;
+        // the presets are not applied again, the state already has their direction
+        self.projection = state.projection;
+        self.pivot_distance = state.pivot_distance;
+        self.zoom = state.zoom;
+        self.update_look_at();
+        self.update_projection();
+    }
+
+    // the same direction moved back until the bounding sphere of the box fits into the view
+    pub fn framing(&self, bounds: &Aabb) -> CameraState {
+        let center = Point3::from((bounds.min + bounds.max) / 2.);
+        let radius = ((bounds.max - bounds.min).norm() / 2.).max(1e-6);
+        let horizontal_fov = 2. * f64::atan(f64::tan(self.fov / 2.) * self.aspect);
+        let distance = radius / f64::sin(self.fov.min(horizontal_fov) / 2.);
+        CameraState {
+            position: center - self.front * distance,
+            pivot_distance: distance,
+            zoom: 1.,
+            ..self.state()
+        }
+    }
+
+    pub fn pivot(&self) -> Point3<f64> {
+        self.position + self.front * self.pivot_distance
+    }
+
+    // view depth of the point from its z in the normalized device coordinates
+    pub fn linear_depth(&self, ndc_z: f64) -> f64 {
+        let (n, f) = (self.z_near, self.z_far);
+        if self.projection.is_orthographic() {
+            n + ndc_z * (f - n)
+        } else {
+            f * n / (f - ndc_z * (f - n))
+        }
+    }
+
+    // where the ray reaching the world point starts, for the orthographic views the rays are parallel
+    pub fn ray_origin(&self, world_point: &Point3<f64>) -> Point3<f64> {
+        if self.projection.is_orthographic() {
+            world_point - self.front * (world_point - self.position).dot(&self.front)
+        } else {
+            self.position
+        }
+    }
+
     fn update_look_at(&mut self) {
         self.look_at_matrix = Camera::compute_look_at(&self.position, &(self.position + self.front), &self.world_up)
     }
@@ -93,28 +359,59 @@ impl Camera {
         self.update_look_at();
     }
 
-    fn rotate_on(&mut self, pitch: f64, yaw: f64, scroll: f64) {
+    // yaw turns around the world up and pitch changes the angle to the horizon, both are plain angles,
+    // so the steps add up to the same direction however the time is split
+    fn rotate_on(&mut self, pitch: f64, yaw: f64, _scroll: f64) {
+        let up = self.world_up.normalize();
+        let elevation = self.front.dot(&up).clamp(-1., 1.).asin();
+        let heading = (self.front - up * self.front.dot(&up)).try_normalize(1e-9)
+            .or_else(|| up.cross(&Vector3::x()).try_normalize(1e-9))
+            .unwrap_or_else(Vector3::z);
+        let right = heading.cross(&up);
+        let heading = heading * yaw.cos() + right * yaw.sin();
 
-        let direction = -self.front;
-        let right = self.world_up.cross(&direction).normalize();
-        let up = direction.cross(&right);
+        // stops short of the poles, where the right axis is lost
+        let limit = FRAC_PI_2 - 1e-3;
+        let elevation = (elevation - pitch).clamp(elevation.min(-limit), elevation.max(limit));
+        self.front = heading * elevation.cos() + up * elevation.sin();
+        self.update_look_at();
+    }
 
-        let camera_rud_matrix = Matrix4::from_columns(&[
-            right.to_homogeneous(),
-            up.to_homogeneous(),
-            direction.to_homogeneous(),
-            Point3::<f64>::origin().to_homogeneous()
-        ]);
+    // tangent of the half of the vertical angle the background is seen through;
+    // the orthographic views narrow it with the zoom, so the background zooms together with the scene
+    fn background_half_tan(&self) -> f64 {
+        let tan = f64::tan(self.fov / 2.);
+        if self.projection.is_orthographic() {
+            tan / self.zoom
+        } else {
+            tan
+        }
+    }
 
-        let desired_front = Vector3::new(
-            yaw.sin(),
-            -pitch.sin(),
-            yaw.cos() * -pitch.cos()
-        ).normalize();
+    // world space direction of the ray going through the point of the screen in normalized device coordinates
+    pub fn get_view_direction(&self, ndc_x: f64, ndc_y: f64) -> Vector3<f64> {
+        let right = self.front.cross(&self.world_up).normalize();
+        let up = right.cross(&self.front).normalize();
+        let tan = self.background_half_tan();
+        (self.front + right * (ndc_x * tan * self.aspect) + up * (ndc_y * tan)).normalize()
+    }
 
-        let new_front_homogenized = camera_rud_matrix * desired_front.to_homogeneous();
-        self.front = Vector3::<f64>::from_homogeneous(new_front_homogenized).unwrap();
-        self.update_look_at();
+    // average angle between the view directions of the neighbouring pixels of the image
+    pub fn pixel_angle(&self, image_height: f64) -> f64 {
+        2. * self.background_half_tan().atan() / image_height
+    }
+
+    // diameter (in pixels of the image of the height) of the blur circle of the point at the view depth;
+    // the sensor size follows from the focal length and the field of view
+    pub fn circle_of_confusion(&self, depth: f64, image_height: f64) -> f64 {
+        let focal_length = self.focal_length / 1000.;
+        let focus_distance = self.focus_distance.max(focal_length * 1.001);
+        let aperture_diameter = focal_length / self.aperture;
+        let sensor_height = 2. * focal_length * f64::tan(self.fov / 2.);
+
+        let coc = aperture_diameter * focal_length * (depth - focus_distance).abs() /
+            (depth.max(1e-6) * (focus_distance - focal_length));
+        coc / sensor_height * image_height
     }
 
     pub fn set_param(&mut self, action_id: u32, action_value: f64) {
@@ -125,14 +422,70 @@ impl Camera {
             11 => self.rotation[0] = action_value,
             12 => self.rotation[1] = action_value,
             13 => self.rotation[2] = action_value,
+            21 => self.focus_distance = action_value.max(1e-3),
+            22 => self.aperture = action_value.max(0.1),
+            23 => self.focal_length = action_value.max(1.),
+            24 => self.autofocus = action_value != 0.,
+            31 => {
+                self.zoom = action_value.max(1e-3);
+                self.update_projection();
+            },
+            32 => {
+                self.pivot_distance = action_value.max(1e-3);
+                self.update_projection();
+            },
+            41 => {
+                self.fov = action_value.clamp(1., 179.).to_radians();
+                self.update_projection();
+            },
+            42 => {
+                self.z_near = action_value.clamp(1e-6, self.z_far * 0.999);
+                self.update_projection();
+            },
+            43 => {
+                self.z_far = action_value.max(self.z_near * 1.001);
+                self.update_projection();
+            },
+            44 => self.auto_clip_planes = action_value != 0.,
+            51 => self.acceleration = action_value.max(0.),
+            52 => self.damping = action_value.max(0.),
             _ => unsafe { log(&"Not recognized action") }
         }
     }
 
-    pub fn tick(&mut self) {
-        // unsafe { log(&format!("front from tick: {} {} {}", self.front[0], self.front[1], self.front[2])) };
-        self.rotate_on(self.rotation[0], self.rotation[1], self.rotation[2]);
-        self.move_on(self.speed);
+    // advances the fly camera by dt seconds
+    pub fn update(&mut self, dt: f64) {
+        let (acceleration, damping) = (self.acceleration, self.damping);
+        let rate = |requested: &Vector3<f64>| if *requested == Vector3::zeros() { damping } else { acceleration };
+
+        let (rotation, angular_velocity) = approach(&self.angular_velocity, &self.rotation, rate(&self.rotation), dt);
+        self.angular_velocity = angular_velocity;
+        let (movement, velocity) = approach(&self.velocity, &self.speed, rate(&self.speed), dt);
+        self.velocity = velocity;
+
+        // the movement is done along the axes turned halfway, so big steps stay close to small ones
+        let half = rotation / 2.;
+        self.rotate_on(half[0], half[1], half[2]);
+        self.move_on(movement);
+        self.rotate_on(half[0], half[1], half[2]);
+
+        // the leftovers of the slowing down are dropped
+        if self.speed == Vector3::zeros() && self.velocity.norm() < 1e-4 {
+            self.velocity = Vector3::zeros();
+        }
+        if self.rotation == Vector3::zeros() && self.angular_velocity.norm() < 1e-4 {
+            self.angular_velocity = Vector3::zeros();
+        }
+    }
+
+    // drops the remaining velocity, the requested speeds stay
+    pub fn stop(&mut self) {
+        self.velocity = Vector3::zeros();
+        self.angular_velocity = Vector3::zeros();
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.velocity != Vector3::zeros() || self.angular_velocity != Vector3::zeros()
     }
 
     pub fn new(
@@ -154,8 +507,89 @@ impl Camera {
             front,
             look_at_matrix: Camera::compute_look_at(&position, &(position + front), &world_up),
             projection_matrix: Camera::compute_projection_matrix(fov, aspect, z_near, z_far),
+            projection: Projection::Perspective,
+            zoom: 1.,
+            pivot_distance: 3.,
+            z_near,
+            z_far,
+            auto_clip_planes: false,
+            focus_distance: 3.,
+            aperture: 2.8,
+            focal_length: 50.,
+            autofocus: false,
+            acceleration: 10.,
+            damping: 10.,
             speed: Vector3::new(0., 0., 0.),
-            rotation: Vector3::new(0., 0., 0.)
+            rotation: Vector3::new(0., 0., 0.),
+            velocity: Vector3::zeros(),
+            angular_velocity: Vector3::zeros()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera {
+        let front = Vector3::new(-1., -1., -1.).normalize();
+        Camera::new(1., 1., 0.1, 10., &Point3::new(1., 1., 1.), &front, &Vector3::new(0., 1., 0.))
+    }
+
+    fn fly(steps: usize) -> Camera {
+        let mut camera = camera();
+        camera.set_param(11, 0.3);
+        camera.set_param(12, 1.);
+        for _ in 0..steps {
+            camera.update(1. / steps as f64);
+        }
+        camera
+    }
+
+    #[test]
+    fn pixel_angle_follows_projection() {
+        let mut camera = Camera::new(
+            FRAC_PI_2, 1., 0.1, 100., &Point3::new(0., 0., 5.), &Vector3::new(0., 0., -1.), &Vector3::new(0., 1., 0.)
+        );
+        assert!((camera.pixel_angle(100.) - FRAC_PI_2 / 100.).abs() < 1e-12);
+
+        // zoomed in twice, the background is seen through the half as wide view
+        camera.set_projection(Projection::Orthographic);
+        camera.set_param(31, 2.);
+        assert!((camera.pixel_angle(100.) - 2. * f64::atan(0.5) / 100.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rotation_does_not_depend_on_frame_rate() {
+        let (slow, fast) = (fly(30), fly(144));
+        assert!((slow.front - fast.front).norm() < 1e-9);
+        assert!((slow.front.norm() - 1.).abs() < 1e-9);
+        assert!((fly(4).front - fast.front).norm() < 1e-9);
+    }
+
+    #[test]
+    fn pitch_stops_at_pole() {
+        let mut camera = camera();
+        camera.set_param(11, -10.);
+        for _ in 0..10 {
+            camera.update(0.1);
+        }
+        assert!(camera.front[1] < 1.);
+        assert!(camera.front[1] > 0.999);
+        assert!(camera.front.iter().all(|value| value.is_finite()));
+    }
+    #[test]
+    fn widening_keeps_user_planes() {
+        let mut camera = camera();
+        let far_away = Aabb { min: Vector3::repeat(-30.), max: Vector3::repeat(-20.) };
+        camera.widen_clip_planes(&far_away, &camera.state());
+        assert_eq!(camera.z_near, 0.1);
+        assert!(camera.z_far > 21. * 3_f64.sqrt());
+
+        // the planes already around the bounds are kept as they are
+        let (z_near, z_far) = (camera.z_near, camera.z_far);
+        let close = Aabb { min: Vector3::repeat(-0.1), max: Vector3::repeat(0.1) };
+        camera.widen_clip_planes(&close, &camera.state());
+        assert_eq!((camera.z_near, camera.z_far), (z_near, z_far));
+    }
+}
@@ -0,0 +1,81 @@
+use crate::types::*;
+
+// what the image shows instead of the lit scene
+#[derive(Copy, Clone, PartialEq)]
+pub enum DebugView {
+    None,
+    // base color without the lighting
+    Albedo,
+    // flat normal of the face
+    FaceNormals,
+    // vertex normals interpolated over the face
+    InterpolatedNormals,
+    // the shading normal, from the normal map when it is used
+    NormalMapNormals,
+    Uv,
+    Depth,
+    ObjectId,
+    FaceId,
+    // how many times the pixel was rasterized
+    Overdraw,
+    // the full lighting of the white material
+    LightingOnly
+}
+
+impl DebugView {
+    pub fn from_id(view_id: u32) -> Option<DebugView> {
+        match view_id {
+            0 => Some(DebugView::None),
+            1 => Some(DebugView::Albedo),
+            2 => Some(DebugView::FaceNormals),
+            3 => Some(DebugView::InterpolatedNormals),
+            4 => Some(DebugView::NormalMapNormals),
+            5 => Some(DebugView::Uv),
+            6 => Some(DebugView::Depth),
+            7 => Some(DebugView::ObjectId),
+            8 => Some(DebugView::FaceId),
+            9 => Some(DebugView::Overdraw),
+            10 => Some(DebugView::LightingOnly),
+            _ => None
+        }
+    }
+
+    // the color is shown as it is, without the lighting, the post effects and the tone mapping
+    pub fn is_unlit(self) -> bool {
+        !matches!(self, DebugView::None | DebugView::LightingOnly)
+    }
+}
+
+// the normal components from [-1:1] to [0:1]
+pub fn normal_color(normal: &Vector3<f64>) -> Color<f64> {
+    Color {
+        r: normal[0] * 0.5 + 0.5,
+        g: normal[1] * 0.5 + 0.5,
+        b: normal[2] * 0.5 + 0.5
+    }
+}
+
+// distinct color for every id, the neighbour ids get unrelated colors
+pub fn id_color(id: usize) -> Color<f64> {
+    let mut hash = (id as u32).wrapping_add(1).wrapping_mul(0x9e37_79b9);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    Color {
+        r: (hash & 0xff) as f64 / 255.,
+        g: ((hash >> 8) & 0xff) as f64 / 255.,
+        b: ((hash >> 16) & 0xff) as f64 / 255.
+    }
+}
+
+// blue - green - yellow - red for t in [0:1]
+pub fn heat_color(t: f64) -> Color<f64> {
+    let t = t.clamp(0., 1.) * 3.;
+    if t < 1. {
+        Color { r: 0., g: t, b: 1. - t }
+    } else if t < 2. {
+        Color { r: t - 1., g: 1., b: 0. }
+    } else {
+        Color { r: 1., g: 3. - t, b: 0. }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::types::*;
+use crate::camera::Camera;
+use crate::console::log;
+
+// angle between the consecutive samples of the spiral
+const GOLDEN_ANGLE: f64 = 2.399_963_229_728_653;
+
+pub struct DepthOfField {
+    pub enabled: bool,
+    // the blur radius (in pixels) is clamped to keep the gather affordable
+    pub max_radius: f64,
+    pub sample_count: usize,
+    // view depth of every pixel, the far plane for the background
+    depth: Vec<f64>,
+    // blur radius in pixels
+    coc: Vec<f64>,
+    source: Vec<Color<f32>>
+}
+
+impl DepthOfField {
+    pub fn new() -> DepthOfField {
+        DepthOfField {
+            enabled: false,
+            max_radius: 8.,
+            sample_count: 32,
+            depth: Vec::new(),
+            coc: Vec::new(),
+            source: Vec::new()
+        }
+    }
+
+    pub fn set_param(&mut self, param_id: u32, param_value: f64) {
+        match param_id {
+            1 => self.enabled = param_value != 0.,
+            2 => self.max_radius = param_value.max(0.),
+            3 => self.sample_count = (param_value.max(1.) as usize).min(256),
+            _ => log("Not recognized depth of field param")
+        }
+    }
+
+    // view depth of the object under the center of the image for the autofocus, None for the background
+    pub fn center_depth(z_buf: &[f64], width: usize, height: usize, to_screen: &Matrix4<f64>) -> Option<f64> {
+        let (x, y) = (width / 2, height / 2);
+        let depth = *z_buf.get(y * width + x)?;
+        if depth == 1. {
+            return None;
+        }
+        let position = to_screen.try_inverse()? * Vector4::new(x as f64, y as f64, depth, 1.);
+        Some(-position[2] / position[3])
+    }
+
+    // z_buf holds the screen space depth (1 - empty), to_screen is the projection followed by the viewport transform
+    pub fn apply(
+        &mut self, color_buf: &mut [Color<f32>], z_buf: &[f64],
+        width: usize, height: usize, to_screen: &Matrix4<f64>, camera: &Camera
+    ) {
+        let to_view = match to_screen.try_inverse() {
+            Some(matrix) => matrix,
+            None => return
+        };
+        self.depth.resize(width * height, 0.);
+        self.coc.resize(width * height, 0.);
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                let position = to_view * Vector4::new(x as f64, y as f64, z_buf[index], 1.);
+                self.depth[index] = -position[2] / position[3];
+            }
+        }
+
+        for (coc, depth) in self.coc.iter_mut().zip(self.depth.iter()) {
+            *coc = (camera.circle_of_confusion(*depth, height as f64) / 2.).min(self.max_radius);
+        }
+
+        self.source.clear();
+        self.source.extend_from_slice(color_buf);
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                let radius = self.coc[index];
+                if radius < 0.5 {
+                    continue;
+                }
+
+                let mut color = self.source[index].to_f64();
+                let mut total_weight = 1.;
+                for i in 0..self.sample_count {
+                    // the spiral fills the disk evenly
+                    let distance = radius * ((i as f64 + 0.5) / self.sample_count as f64).sqrt();
+                    let angle = i as f64 * GOLDEN_ANGLE;
+                    let sx = (x as f64 + distance * angle.cos()).round();
+                    let sy = (y as f64 + distance * angle.sin()).round();
+                    if sx < 0. || sy < 0. || sx >= width as f64 || sy >= height as f64 {
+                        continue;
+                    }
+                    let sample_index = sy as usize * width + sx as usize;
+
+                    // the sharp foreground does not bleed into the blurred background, so the closer
+                    // sample contributes only when its own blur circle reaches the pixel
+                    let reach = if self.depth[sample_index] < self.depth[index] { self.coc[sample_index] } else { radius };
+                    let weight = ((reach - distance) + 1.).clamp(0., 1.);
+                    color = color + self.source[sample_index].to_f64() * weight;
+                    total_weight += weight;
+                }
+                color_buf[index] = (color * (1. / total_weight)).to_f32();
+            }
+        }
+    }
+}
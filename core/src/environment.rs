@@ -0,0 +1,440 @@
+use std::f64::consts::PI;
+
+use crate::types::*;
+use crate::texture::{Texture, TextureFilter, TextureWrap};
+use crate::console::log;
+
+// amount of the prefiltered specular levels, roughness of the level i is i / (count - 1)
+const SPECULAR_LEVELS: usize = 6;
+const PREFILTERED_WIDTH: usize = 32;
+const PREFILTER_SOURCE_WIDTH: usize = 64;
+
+pub struct Environment {
+    pub enabled: bool,
+    pub intensity: f64,
+    // rotation around the world y axis in radians
+    pub rotation: f64,
+    // raw content of the radiance .hdr file, written from js
+    pub source: Vec<u8>,
+    // 0 - the environment itself, the rest are prefiltered for the growing roughness
+    specular_levels: Vec<Texture<Color<f64>>>,
+    // spherical harmonics (9 coefficients) of the environment radiance
+    irradiance_sh: [Color<f64>; 9]
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment {
+            enabled: false,
+            intensity: 1.,
+            rotation: 0.,
+            source: Vec::new(),
+            specular_levels: Vec::new(),
+            irradiance_sh: [Color { r: 0., g: 0., b: 0. }; 9]
+        }
+    }
+
+    pub fn set_param(&mut self, param_id: u32, param_value: f64) {
+        match param_id {
+            1 => self.enabled = param_value != 0. && !self.specular_levels.is_empty(),
+            2 => self.intensity = param_value,
+            3 => self.rotation = param_value,
+            _ => log("Not recognized environment param")
+        }
+    }
+
+    // parses the source and precomputes the lighting, the source is released afterwards
+    pub fn load(&mut self) -> bool {
+        let (width, height, radiance) = match parse_hdr(&self.source) {
+            Some(parsed) => parsed,
+            None => {
+                log("Cannot parse the environment map");
+                return false;
+            }
+        };
+        self.source = Vec::new();
+
+        let mut texture = Texture::new();
+        texture.set_size(width, height, Color { r: 0., g: 0., b: 0. });
+        texture.data = radiance;
+        texture.sampler.filter = TextureFilter::Bilinear;
+        texture.sampler.wrap_u = TextureWrap::Repeat;
+        texture.sampler.wrap_v = TextureWrap::ClampToEdge;
+        texture.generate_mips();
+
+        // the convolutions use a small copy of the environment
+        let mut source_level = 0;
+        while source_level < texture.mips.len() && level_width(&texture, source_level) > PREFILTER_SOURCE_WIDTH {
+            source_level += 1;
+        }
+        let source = directional_samples(&texture, source_level);
+
+        self.irradiance_sh = project_sh(&source);
+
+        self.specular_levels = vec![texture];
+        for level in 1..SPECULAR_LEVELS {
+            let roughness = level as f64 / (SPECULAR_LEVELS - 1) as f64;
+            self.specular_levels.push(prefilter(&source, roughness));
+        }
+
+        self.enabled = true;
+        true
+    }
+
+    fn to_environment_space(&self, direction: &Vector3<f64>) -> Vector3<f64> {
+        let sin = self.rotation.sin();
+        let cos = self.rotation.cos();
+        Vector3::new(
+            direction[0] * cos + direction[2] * sin,
+            direction[1],
+            -direction[0] * sin + direction[2] * cos
+        )
+    }
+
+    // cosine-weighted integral of the incoming radiance around the normal
+    pub fn irradiance(&self, normal: &Vector3<f64>) -> Color<f64> {
+        let n = self.to_environment_space(normal);
+        let (x, y, z) = (n[0], n[1], n[2]);
+        let sh = &self.irradiance_sh;
+
+        let c1 = 0.429043;
+        let c2 = 0.511664;
+        let c3 = 0.743125;
+        let c4 = 0.886227;
+        let c5 = 0.247708;
+
+        let irradiance =
+            sh[8] * (c1 * (x * x - y * y)) +
+            sh[6] * (c3 * z * z) +
+            sh[0] * c4 -
+            sh[6] * c5 +
+            (sh[4] * (x * y) + sh[7] * (x * z) + sh[5] * (y * z)) * (2. * c1) +
+            (sh[3] * x + sh[1] * y + sh[2] * z) * (2. * c2);
+
+        Color {
+            r: irradiance.r.max(0.),
+            g: irradiance.g.max(0.),
+            b: irradiance.b.max(0.)
+        } * self.intensity
+    }
+
+    // incoming radiance from the direction, blurred according to the roughness
+    pub fn radiance(&self, direction: &Vector3<f64>, roughness: f64) -> Color<f64> {
+        let (u, v) = direction_to_uv(&self.to_environment_space(direction));
+        let level = roughness.clamp(0., 1.) * (self.specular_levels.len() - 1) as f64;
+        let lower = level.floor() as usize;
+        let t = level - lower as f64;
+
+        let color = if t == 0. {
+            self.specular_levels[lower].sample(u, v, 0.)
+        } else {
+            self.specular_levels[lower].sample(u, v, 0.) * (1. - t) +
+            self.specular_levels[lower + 1].sample(u, v, 0.) * t
+        };
+        color * self.intensity
+    }
+}
+
+pub fn direction_to_uv(direction: &Vector3<f64>) -> (f64, f64) {
+    let direction = direction.normalize();
+    let u = 0.5 + direction[0].atan2(-direction[2]) / (2. * PI);
+    let v = 1. - direction[1].clamp(-1., 1.).acos() / PI;
+    (u, v)
+}
+
+fn uv_to_direction(u: f64, v: f64) -> Vector3<f64> {
+    let phi = (u - 0.5) * 2. * PI;
+    let theta = (1. - v) * PI;
+    Vector3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+}
+
+fn level_width(texture: &Texture<Color<f64>>, level: usize) -> usize {
+    if level == 0 { texture.width } else { texture.mips[level - 1].width }
+}
+
+// direction, radiance and solid angle of each texel of the level
+fn directional_samples(texture: &Texture<Color<f64>>, level: usize) -> Vec<(Vector3<f64>, Color<f64>, f64)> {
+    let (width, height, data) = if level == 0 {
+        (texture.width, texture.height, &texture.data)
+    } else {
+        let mip = &texture.mips[level - 1];
+        (mip.width, mip.height, &mip.data)
+    };
+
+    let mut samples = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let v = 1. - (y as f64 + 0.5) / height as f64;
+        let theta = (1. - v) * PI;
+        let solid_angle = (2. * PI / width as f64) * (PI / height as f64) * theta.sin();
+        for x in 0..width {
+            let u = (x as f64 + 0.5) / width as f64;
+            samples.push((uv_to_direction(u, v), data[y * width + x], solid_angle));
+        }
+    }
+    samples
+}
+
+fn project_sh(samples: &[(Vector3<f64>, Color<f64>, f64)]) -> [Color<f64>; 9] {
+    let mut sh = [Color { r: 0., g: 0., b: 0. }; 9];
+    for (direction, radiance, solid_angle) in samples.iter() {
+        let (x, y, z) = (direction[0], direction[1], direction[2]);
+        let basis = [
+            0.282095,
+            0.488603 * y,
+            0.488603 * z,
+            0.488603 * x,
+            1.092548 * x * y,
+            1.092548 * y * z,
+            0.315392 * (3. * z * z - 1.),
+            1.092548 * x * z,
+            0.546274 * (x * x - y * y)
+        ];
+        for (coefficient, value) in sh.iter_mut().zip(basis.iter()) {
+            *coefficient = *coefficient + *radiance * (value * solid_angle);
+        }
+    }
+    sh
+}
+
+// convolution with the specular lobe of the roughness (phong approximation of ggx)
+fn prefilter(samples: &[(Vector3<f64>, Color<f64>, f64)], roughness: f64) -> Texture<Color<f64>> {
+    let width = PREFILTERED_WIDTH;
+    let height = PREFILTERED_WIDTH / 2;
+    let exponent = (2. / roughness.powi(4).max(1e-4) - 2.).max(1.);
+
+    let mut texture = Texture::new();
+    texture.set_size(width, height, Color { r: 0., g: 0., b: 0. });
+    texture.sampler.filter = TextureFilter::Bilinear;
+    texture.sampler.wrap_u = TextureWrap::Repeat;
+    texture.sampler.wrap_v = TextureWrap::ClampToEdge;
+
+    for y in 0..height {
+        for x in 0..width {
+            let u = (x as f64 + 0.5) / width as f64;
+            let v = 1. - (y as f64 + 0.5) / height as f64;
+            let reflection = uv_to_direction(u, v);
+
+            let mut color = Color { r: 0., g: 0., b: 0. };
+            let mut total_weight = 0.;
+            for (direction, radiance, solid_angle) in samples.iter() {
+                let cos = reflection.dot(direction);
+                if cos <= 0. {
+                    continue;
+                }
+                let weight = cos.powf(exponent) * solid_angle;
+                color = color + *radiance * weight;
+                total_weight += weight;
+            }
+            if total_weight > 0. {
+                texture.data[y * width + x] = color * (1. / total_weight);
+            }
+        }
+    }
+    texture
+}
+
+fn read_line(data: &[u8], position: &mut usize) -> Option<String> {
+    let start = *position;
+    while *position < data.len() && data[*position] != b'\n' {
+        *position += 1;
+    }
+    if *position >= data.len() {
+        return None;
+    }
+    *position += 1;
+    Some(String::from_utf8_lossy(&data[start..*position - 1]).trim().to_string())
+}
+
+fn rgbe_to_color(rgbe: &[u8]) -> Color<f64> {
+    if rgbe[3] == 0 {
+        return Color { r: 0., g: 0., b: 0. };
+    }
+    let f = 2f64.powi(rgbe[3] as i32 - (128 + 8));
+    Color {
+        r: rgbe[0] as f64 * f,
+        g: rgbe[1] as f64 * f,
+        b: rgbe[2] as f64 * f
+    }
+}
+
+// radiance rgbe (.hdr) file, only the standard -Y height +X width orientation is supported
+pub fn parse_hdr(data: &[u8]) -> Option<(usize, usize, Vec<Color<f64>>)> {
+    let mut position = 0;
+    let magic = read_line(data, &mut position)?;
+    if !magic.starts_with("#?") {
+        return None;
+    }
+    loop {
+        let line = read_line(data, &mut position)?;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return None;
+        }
+    }
+
+    let resolution = read_line(data, &mut position)?;
+    let parts: Vec<&str> = resolution.split_whitespace().collect();
+    if parts.len() != 4 || parts[0] != "-Y" || parts[2] != "+X" {
+        return None;
+    }
+    let height: usize = parts[1].parse().ok()?;
+    let width: usize = parts[3].parse().ok()?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let mut colors = Vec::with_capacity(width * height);
+    let mut scanline = vec![0u8; width * 4];
+    for _ in 0..height {
+        let header = data.get(position..position + 4)?;
+        let is_rle = (8..32768).contains(&width) && header[0] == 2 && header[1] == 2 && header[2] & 0x80 == 0;
+
+        if !is_rle {
+            // flat scanline
+            let flat = data.get(position..position + width * 4)?;
+            scanline.copy_from_slice(flat);
+            position += width * 4;
+        } else {
+            if ((header[2] as usize) << 8 | header[3] as usize) != width {
+                return None;
+            }
+            position += 4;
+            // components are stored separately, each one run-length encoded
+            for component in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = *data.get(position)? as usize;
+                    position += 1;
+                    if count > 128 {
+                        let count = count - 128;
+                        let value = *data.get(position)?;
+                        position += 1;
+                        if x + count > width {
+                            return None;
+                        }
+                        for _ in 0..count {
+                            scanline[x * 4 + component] = value;
+                            x += 1;
+                        }
+                    } else {
+                        if count == 0 || x + count > width {
+                            return None;
+                        }
+                        let values = data.get(position..position + count)?;
+                        position += count;
+                        for value in values.iter() {
+                            scanline[x * 4 + component] = *value;
+                            x += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        for x in 0..width {
+            colors.push(rgbe_to_color(&scanline[x * 4..x * 4 + 4]));
+        }
+    }
+
+    Some((width, height, colors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(width: usize, height: usize) -> Vec<u8> {
+        format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width).into_bytes()
+    }
+
+    // flat scanlines of one rgbe value
+    fn flat_file(width: usize, height: usize, rgbe: [u8; 4]) -> Vec<u8> {
+        let mut data = header(width, height);
+        for _ in 0..width * height {
+            data.extend_from_slice(&rgbe);
+        }
+        data
+    }
+
+    #[test]
+    fn parses_flat_scanlines() {
+        // 128 * 2^(129 - 136) = 1
+        let (width, height, colors) = parse_hdr(&flat_file(3, 2, [128, 64, 0, 129])).unwrap();
+        assert_eq!((width, height, colors.len()), (3, 2, 6));
+        assert!(colors.iter().all(|color| (color.r, color.g, color.b) == (1., 0.5, 0.)));
+    }
+
+    #[test]
+    fn parses_rle_scanlines() {
+        let mut data = header(8, 1);
+        data.extend_from_slice(&[2, 2, 0, 8]);
+        // red as one run, green as literal values, blue and exponent as runs
+        data.extend_from_slice(&[128 + 8, 128]);
+        data.push(8);
+        data.extend((0..8).map(|x| x * 16));
+        data.extend_from_slice(&[128 + 8, 0]);
+        data.extend_from_slice(&[128 + 8, 129]);
+
+        let (width, height, colors) = parse_hdr(&data).unwrap();
+        assert_eq!((width, height), (8, 1));
+        for (x, color) in colors.iter().enumerate() {
+            assert_eq!((color.r, color.g, color.b), (1., x as f64 * 16. / 128., 0.));
+        }
+
+        // cut in the middle of the runs
+        assert!(parse_hdr(&data[..data.len() - 3]).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_and_empty_files() {
+        let data = flat_file(3, 2, [128, 64, 0, 129]);
+        assert!(parse_hdr(&data[..data.len() - 1]).is_none());
+        assert!(parse_hdr(&header(3, 2)[..10]).is_none());
+        // data follows the header, only the size makes them invalid
+        for (width, height) in [(0, 4), (4, 0)] {
+            let mut data = header(width, height);
+            data.extend_from_slice(&[0; 16]);
+            assert!(parse_hdr(&data).is_none());
+        }
+        assert!(parse_hdr(&[]).is_none());
+    }
+
+    #[test]
+    fn uniform_environment_lights_evenly() {
+        let mut environment = Environment::new();
+        environment.source = flat_file(64, 32, [128, 128, 128, 129]);
+        assert!(environment.load());
+
+        // the cosine-weighted integral of the constant radiance 1 is pi
+        for normal in [Vector3::new(0., 1., 0.), Vector3::new(0., -1., 0.), Vector3::new(1., 1., 1.).normalize()] {
+            let irradiance = environment.irradiance(&normal);
+            assert!((irradiance.r - PI).abs() < PI * 0.02);
+            for roughness in [0., 0.5, 1.] {
+                assert!((environment.radiance(&normal, roughness).g - 1.).abs() < 0.02);
+            }
+        }
+    }
+
+    #[test]
+    fn bright_sky_lights_from_above() {
+        // upper half of the panorama bright, lower half dark
+        let (width, height) = (64, 32);
+        let mut data = header(width, height);
+        for y in 0..height {
+            let rgbe = if y < height / 2 { [128, 128, 128, 129] } else { [0, 0, 0, 0] };
+            for _ in 0..width {
+                data.extend_from_slice(&rgbe);
+            }
+        }
+        let mut environment = Environment::new();
+        environment.source = data;
+        assert!(environment.load());
+
+        let up = Vector3::new(0., 1., 0.);
+        let down = Vector3::new(0., -1., 0.);
+        assert!(environment.irradiance(&up).r > environment.irradiance(&down).r * 10.);
+        assert!(environment.radiance(&up, 1.).r > environment.radiance(&down, 1.).r);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::types::*;
+use crate::console::log;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum FogMode {
+    None,
+    // fades between the start and the end distances
+    Linear,
+    Exponential,
+    ExponentialSquared
+}
+
+impl FogMode {
+    pub fn from_id(mode_id: u32) -> Option<FogMode> {
+        match mode_id {
+            0 => Some(FogMode::None),
+            1 => Some(FogMode::Linear),
+            2 => Some(FogMode::Exponential),
+            3 => Some(FogMode::ExponentialSquared),
+            _ => None
+        }
+    }
+}
+
+pub struct Fog {
+    pub mode: FogMode,
+    pub color: Color<f64>,
+    // exponential modes
+    pub density: f64,
+    // linear mode
+    pub start: f64,
+    pub end: f64,
+    // height fog is added on top of the distance fog, its density falls off exponentially above base_height
+    pub height_density: f64,
+    pub height_falloff: f64,
+    pub base_height: f64
+}
+
+impl Fog {
+    pub fn new() -> Fog {
+        Fog {
+            mode: FogMode::None,
+            color: Color { r: 0.5, g: 0.5, b: 0.5 },
+            density: 0.1,
+            start: 1.,
+            end: 10.,
+            height_density: 0.,
+            height_falloff: 1.,
+            base_height: 0.
+        }
+    }
+
+    pub fn set_param(&mut self, param_id: u32, param_value: f64) {
+        match param_id {
+            1 => self.density = param_value.max(0.),
+            2 => self.start = param_value,
+            3 => self.end = param_value,
+            4 => self.height_density = param_value.max(0.),
+            5 => self.height_falloff = param_value.max(1e-6),
+            6 => self.base_height = param_value,
+            _ => log("Not recognized fog param")
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.mode != FogMode::None || self.height_density > 0.
+    }
+
+    // part of the surface light reaching the camera, depth is the linear depth of the point
+    pub fn transmittance(&self, depth: f64, camera_position: &Point3<f64>, world_point: &Point3<f64>) -> f64 {
+        let depth = depth.max(0.);
+        let distance_transmittance = match self.mode {
+            FogMode::None => 1.,
+            FogMode::Linear => ((self.end - depth) / (self.end - self.start).max(1e-6)).clamp(0., 1.),
+            FogMode::Exponential => (-self.density * depth).exp(),
+            FogMode::ExponentialSquared => (-(self.density * depth).powi(2)).exp()
+        };
+        if self.height_density == 0. {
+            return distance_transmittance;
+        }
+
+        // density integrated along the ray from the camera to the point
+        let ray = world_point - camera_position;
+        let length = ray.norm();
+        let rise = ray[1] * self.height_falloff;
+        let camera_density = self.height_density * (-self.height_falloff * (camera_position[1] - self.base_height)).exp();
+        let integral = if rise.abs() < 1e-6 {
+            camera_density * length
+        } else {
+            camera_density * length * (1. - (-rise).exp()) / rise
+        };
+        distance_transmittance * (-integral).exp()
+    }
+}
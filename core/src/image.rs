@@ -1,9 +1,30 @@
+use std::rc::Rc;
+use std::collections::BTreeMap;
+
 use wasm_bindgen::prelude::wasm_bindgen;
 
 use crate::types::*;
 use crate::raster;
 use crate::world::World;
-use crate::camera::Camera;
+use crate::camera::{Camera, CameraState, Projection};
+use crate::orbit::OrbitController;
+use crate::transition::{CameraTransition, Easing};
+use crate::path::{CameraPath, PathCurve, PathLook};
+use crate::bounds::{Aabb, BoundingBox};
+use crate::shadow::ShadowMap;
+use crate::light::LightKind;
+use crate::object::ShadingModel;
+use crate::shader::{Shader, Fragment, Surface, ShadingContext};
+use crate::background::BackgroundMode;
+use crate::tonemap::{ToneMapper, ToneMapping};
+use crate::bloom::Bloom;
+use crate::ssao::Ssao;
+use crate::dof::DepthOfField;
+use crate::fog::FogMode;
+use crate::selection::Selection;
+use crate::debug::{self, DebugView};
+use crate::pick::{PickResult, RayHit};
+use crate::texture::ColorSpace;
 use crate::utils::set_panic_hook;
 
 #[wasm_bindgen]
@@ -12,18 +33,35 @@ pub struct Image {
     pub height: u32,
     pixels: Vec<Pixel>,
     // buffers
-    emission_buf: Vec<Pixel>,
-    pingpong_emission_buf: Vec<Pixel>,
+    // linear hdr color, tone mapped into pixels at the end of the frame
+    color_buf: Vec<Color<f32>>,
+    // ambient part of the color, added to color_buf after the ambient occlusion pass
+    ambient_buf: Vec<Color<f32>>,
+    // view space normals of the shaded pixels
+    normal_buf: Vec<Vector3<f64>>,
     z_buf: Vec<f64>,
     face_index_buffer: Vec<i32>,
     object_index_buffer: Vec<i32>,
 
     world: World,
     camera: Camera,
+    orbit: OrbitController,
+    transition: Option<CameraTransition>,
+    transition_easing: Easing,
+    camera_bookmarks: BTreeMap<String, CameraState>,
+    camera_path: CameraPath,
+    shadow_map: ShadowMap,
+    tone_mapper: ToneMapper,
+    bloom: Bloom,
+    ssao: Ssao,
+    depth_of_field: DepthOfField,
+    selection: Selection,
+    debug_view: DebugView,
     to_screen_matrix: Matrix4<f64>
 }
 
-static blur_weights: &'static [f64] = &[0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216];
+// emission maps are in [0:1], the emitted light is brighter than the bloom threshold
+const EMISSION_STRENGTH: f64 = 1.5;
 
 #[wasm_bindgen]
 impl Image {
@@ -41,32 +79,9 @@ impl Image {
             })
             .collect();
         
-        let emission_buf = (0..width * height)
-        .map(|_| {
-            Pixel {
-                color: Color {
-                    r: 0,
-                    g: 0,
-                    b: 0,           
-                },
-                a: 255
-            }
-        })
-        .collect();
-
-        let pingpong_emission_buf = (0..width * height)
-        .map(|_| {
-            Pixel {
-                color: Color {
-                    r: 0,
-                    g: 0,
-                    b: 0,           
-                },
-                a: 255
-            }
-        })
-        .collect();
-
+        let color_buf = vec![Color { r: 0., g: 0., b: 0. }; (width * height) as usize];
+        let ambient_buf = vec![Color { r: 0., g: 0., b: 0. }; (width * height) as usize];
+        let normal_buf = vec![Vector3::new(0., 0., 1.); (width * height) as usize];
         let z_buf = vec![1.; (width * height) as usize];
         let face_buffer = vec![-1; (width * height) as usize];
         let object_index_buffer = vec![-1; (width * height) as usize];
@@ -83,7 +98,7 @@ impl Image {
 
         let world = World::new();
 
-        let camera = Camera::new(
+        let mut camera = Camera::new(
             std::f64::consts::PI / 4.,
             f_width / f_height,
             0.1,
@@ -92,18 +107,33 @@ impl Image {
             &Vector3::new(-1., -1., -1.).normalize(),
             &Vector3::new(0., 1., 0.)
         );
+        // the views are framed around the origin
+        camera.pivot_distance = camera.position.coords.norm();
                         
         Image {
             width,
             height,
             pixels,
-            emission_buf,
-            pingpong_emission_buf,
+            color_buf,
+            ambient_buf,
+            normal_buf,
             z_buf,
             face_index_buffer: face_buffer,
             object_index_buffer,
             world,
             camera,
+            orbit: OrbitController::new(),
+            transition: None,
+            transition_easing: Easing::EaseInOut,
+            camera_bookmarks: BTreeMap::new(),
+            camera_path: CameraPath::new(),
+            shadow_map: ShadowMap::new(),
+            tone_mapper: ToneMapper::new(),
+            bloom: Bloom::new(),
+            ssao: Ssao::new(),
+            depth_of_field: DepthOfField::new(),
+            selection: Selection::new(),
+            debug_view: DebugView::None,
             to_screen_matrix
         }
     }
@@ -114,7 +144,6 @@ impl Image {
 
     pub fn get_pixels(&self) -> *const Pixel {
         self.pixels.as_ptr()
-        // self.emission_buf.as_ptr()
     }
 
     pub fn add_object_vertex(&mut self, object_handle: usize, x:f64, y:f64, z:f64) {
@@ -151,6 +180,22 @@ impl Image {
         self.world.set_object_color(object_handle, r, g, b);
     }
 
+    // model_id: 1 - phong, 2 - pbr (metallic-roughness)
+    pub fn set_object_shading_model(&mut self, object_handle: usize, model_id: u32) {
+        if let Some(model) = ShadingModel::from_id(model_id) {
+            self.world.set_object_shading_model(object_handle, model);
+        }
+    }
+
+    pub fn set_object_metallic(&mut self, object_handle: usize, metallic: f64) {
+        self.world.set_object_metallic(object_handle, metallic);
+    }
+
+    pub fn set_object_roughness(&mut self, object_handle: usize, roughness: f64) {
+        self.world.set_object_roughness(object_handle, roughness);
+    }
+
+    // texture_index: 1 - diffuse, 2 - normal, 3 - specular, 4 - emission, 5 - metallic, 6 - roughness, 7 - ambient occlusion
     pub fn set_object_texture_size(&mut self, object_handle: usize, texture_index: usize, width: usize, height: usize) {
         self.world.set_object_texture_size(object_handle, texture_index, width, height);
     }
@@ -163,13 +208,526 @@ impl Image {
         self.world.get_object_texture_pixels(object_handle, texture_index)
     }
 
+    // srgb: true for the color data (diffuse, emission), false for the linear data (normals, roughness...)
+    pub fn set_object_texture_color_space(&mut self, object_handle: usize, texture_index: usize, srgb: bool) {
+        let color_space = if srgb { ColorSpace::Srgb } else { ColorSpace::Linear };
+        self.world.set_object_texture_color_space(object_handle, texture_index, color_space);
+    }
+
+    // filter_id: 0 - nearest, 1 - bilinear, 2 - trilinear
+    pub fn set_object_texture_filter(&mut self, object_handle: usize, texture_index: usize, filter_id: u32) {
+        self.world.set_object_texture_filter(object_handle, texture_index, filter_id);
+    }
+
+    // wrap id: 0 - repeat, 1 - mirrored repeat, 2 - clamp to edge, 3 - clamp to border
+    pub fn set_object_texture_wrap(&mut self, object_handle: usize, texture_index: usize, wrap_u_id: u32, wrap_v_id: u32) {
+        self.world.set_object_texture_wrap(object_handle, texture_index, wrap_u_id, wrap_v_id);
+    }
+
+    pub fn set_object_texture_border_color(&mut self, object_handle: usize, texture_index: usize, r: u8, g: u8, b: u8) {
+        self.world.set_object_texture_border_color(object_handle, texture_index, r, g, b);
+    }
 
+    pub fn set_object_texture_offset(&mut self, object_handle: usize, texture_index: usize, u: f64, v: f64) {
+        self.world.set_object_texture_offset(object_handle, texture_index, u, v);
+    }
+
+    pub fn set_object_texture_scale(&mut self, object_handle: usize, texture_index: usize, u: f64, v: f64) {
+        self.world.set_object_texture_scale(object_handle, texture_index, u, v);
+    }
+
+    pub fn set_object_texture_rotation(&mut self, object_handle: usize, texture_index: usize, angle: f64) {
+        self.world.set_object_texture_rotation(object_handle, texture_index, angle);
+    }
+
+    // 1..3 - movement speed in units per second, 11..13 - rotation in radians per second,
+    // 21 - focus distance, 22 - aperture (f-number), 23 - focal length in mm, 24 - autofocus (0 / 1),
+    // 31 - orthographic zoom, 32 - pivot distance,
+    // 41 - vertical field of view in degrees, 42 - near plane, 43 - far plane, 44 - fit the planes to the scene (0 / 1),
+    // 51 - acceleration, 52 - damping (1 / seconds, how fast the requested speed is reached and lost)
     pub fn set_camera_param(&mut self, param_id: u32, param_value: f64) {
         self.camera.set_param(param_id, param_value);
     }
 
-    pub fn set_light_param(&mut self, param_id: u32, param_value: f64) {
+    // 0 - perspective, 1 - orthographic, 2 - isometric, 3 - dimetric;
+    // the view around the pivot keeps its size when switching
+    pub fn set_camera_projection(&mut self, projection_id: u32) {
+        if let Some(projection) = Projection::from_id(projection_id) {
+            self.camera.set_projection(projection);
+        }
+    }
+
+    // 0 - fly camera moved by the speed params, 1 - orbit around the pivot driven by the mouse input
+    pub fn set_camera_controller(&mut self, controller_id: u32) {
+        match controller_id {
+            0 => self.orbit.enabled = false,
+            1 => self.orbit.attach(&self.camera),
+            _ => {}
+        }
+    }
+
+    // 1 - rotation speed in radians per pixel, 2 - zoom per wheel step (0.1 - 10%), 3 - inertia in seconds (0 - none),
+    // 4, 5 - min and max pitch in degrees, 6, 7 - min and max distance to the target
+    pub fn set_orbit_param(&mut self, param_id: u32, param_value: f64) {
+        self.orbit.set_param(param_id, param_value);
+    }
+
+    pub fn set_orbit_target(&mut self, x: f64, y: f64, z: f64) {
+        self.orbit.target = Point3::new(x, y, z);
+    }
+
+    // mouse movement in pixels
+    pub fn orbit_rotate(&mut self, dx: f64, dy: f64) {
+        if self.orbit.enabled {
+            self.orbit.rotate(dx, dy);
+        }
+    }
+
+    pub fn orbit_pan(&mut self, dx: f64, dy: f64) {
+        if self.orbit.enabled {
+            self.orbit.pan(dx, dy, &self.camera, self.height as f64);
+        }
+    }
+
+    // wheel steps, positive come closer to the point under the cursor at the pixel x, y
+    pub fn orbit_zoom(&mut self, steps: f64, x: f64, y: f64) {
+        if self.orbit.enabled {
+            let ndc_x = x / self.width as f64 * 2. - 1.;
+            let ndc_y = 1. - y / self.height as f64 * 2.;
+            self.orbit.zoom(steps, ndc_x, ndc_y, &self.camera);
+        }
+    }
+
+    // the camera keeps moving after the input because of the inertia
+    pub fn is_camera_moving(&self) -> bool {
+        self.camera_path.playing || self.transition.is_some() || if self.orbit.enabled { self.orbit.is_moving() } else { self.camera.is_moving() }
+    }
+
+    // advances the camera by dt seconds, compute draws it where it is;
+    // the motion only depends on the inputs and dt, not on the wall clock
+    pub fn update(&mut self, dt: f64) {
+        let dt = dt.max(0.);
+        if self.camera_path.playing {
+            // compute puts the camera on the path, the controllers continue from there after the end
+            self.camera_path.update(dt);
+            if !self.camera_path.playing {
+                self.camera_path.place(&mut self.camera);
+                self.attach_controller();
+            }
+        } else if let Some(transition) = self.transition.as_mut() {
+            // the controllers wait for the transition to finish, then continue from where it stopped
+            if transition.update(dt, &mut self.camera) {
+                self.transition = None;
+                self.attach_controller();
+            }
+        } else if self.orbit.enabled {
+            self.orbit.update(dt, &mut self.camera);
+        } else {
+            self.camera.update(dt);
+        }
+    }
+
+    pub fn get_world_bounds(&self) -> Option<BoundingBox> {
+        self.world.bounds().to_bounding_box()
+    }
+
+    pub fn get_object_bounds(&self, object_handle: usize) -> Option<BoundingBox> {
+        self.world.object_bounds(object_handle)?.to_bounding_box()
+    }
+
+    // moves the camera back along its direction until the whole scene is in the view,
+    // over the duration in seconds (0 - at once)
+    pub fn frame_all(&mut self, duration: f64) {
+        self.frame_bounds(&self.world.bounds(), duration);
+    }
+
+    pub fn frame_object(&mut self, object_handle: usize, duration: f64) {
+        if let Some(bounds) = self.world.object_bounds(object_handle) {
+            self.frame_bounds(&bounds, duration);
+        }
+    }
+
+    // keeps the current view under the name, replacing the older one
+    pub fn save_camera_bookmark(&mut self, name: &str) {
+        self.camera_bookmarks.insert(name.to_string(), self.camera.state());
+    }
+
+    // moves the camera to the saved view over the duration in seconds (0 - at once); false for unknown name
+    pub fn restore_camera_bookmark(&mut self, name: &str, duration: f64) -> bool {
+        match self.camera_bookmarks.get(name) {
+            Some(state) => {
+                self.move_camera_to(*state, duration);
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn remove_camera_bookmark(&mut self, name: &str) -> bool {
+        self.camera_bookmarks.remove(name).is_some()
+    }
+
+    // in the alphabetical order
+    pub fn get_camera_bookmark_names(&self) -> Vec<String> {
+        self.camera_bookmarks.keys().cloned().collect()
+    }
+
+    pub fn clear_camera_path(&mut self) {
+        self.camera_path.clear();
+    }
+
+    pub fn add_camera_path_point(&mut self, x: f64, y: f64, z: f64) {
+        self.camera_path.add_point(Point3::new(x, y, z));
+    }
+
+    // the camera direction at the point, used by the point directions look mode
+    pub fn set_camera_path_point_direction(&mut self, point_index: usize, x: f64, y: f64, z: f64) {
+        self.camera_path.set_point_direction(point_index, Vector3::new(x, y, z));
+    }
+
+    // 0 - catmull-rom through the points, 1 - cubic bezier segments (point, handle, handle, point, ...)
+    pub fn set_camera_path_curve(&mut self, curve_id: u32) {
+        if let Some(curve) = PathCurve::from_id(curve_id) {
+            self.camera_path.set_curve(curve);
+        }
+    }
+
+    // 0 - along the path, 1 - at the target point, 2 - the directions of the points
+    pub fn set_camera_path_look(&mut self, look_id: u32) {
+        if let Some(look) = PathLook::from_id(look_id) {
+            self.camera_path.look = look;
+        }
+    }
+
+    pub fn set_camera_path_target(&mut self, x: f64, y: f64, z: f64) {
+        self.camera_path.target = Point3::new(x, y, z);
+    }
+
+    // 1 - speed in units per second (negative goes back), 2 - loop (0 / 1), 3 - closed (0 / 1)
+    pub fn set_camera_path_param(&mut self, param_id: u32, param_value: f64) {
+        self.camera_path.set_param(param_id, param_value);
+    }
+
+    pub fn play_camera_path(&mut self) {
+        self.transition = None;
+        self.camera_path.play();
+    }
+
+    pub fn pause_camera_path(&mut self) {
+        if self.camera_path.playing {
+            self.camera_path.playing = false;
+            self.attach_controller();
+        }
+    }
+
+    // back to the start, the camera stays where it is
+    pub fn stop_camera_path(&mut self) {
+        let was_playing = self.camera_path.playing;
+        self.camera_path.stop();
+        if was_playing {
+            self.attach_controller();
+        }
+    }
+
+    // jumps to the part of the path (0..1) and puts the camera there
+    pub fn seek_camera_path(&mut self, progress: f64) {
+        self.transition = None;
+        self.camera_path.seek(progress);
+        self.camera_path.place(&mut self.camera);
+        if !self.camera_path.playing {
+            self.attach_controller();
+        }
+    }
+
+    pub fn get_camera_path_progress(&mut self) -> f64 {
+        self.camera_path.progress()
+    }
+
+    pub fn get_camera_path_length(&mut self) -> f64 {
+        self.camera_path.length()
+    }
+
+    // easing of the camera transitions: 0 - linear, 1 - ease in-out, 2 - cubic ease in-out
+    pub fn set_camera_transition_easing(&mut self, easing_id: u32) {
+        if let Some(easing) = Easing::from_id(easing_id) {
+            self.transition_easing = easing;
+        }
+    }
+
+    fn frame_bounds(&mut self, bounds: &Aabb, duration: f64) {
+        if bounds.is_empty() {
+            return;
+        }
+        let state = self.camera.framing(bounds);
+        // the fixed planes of the user could cut the framed bounds, they are widened for the both ends
+        // of the move; the direction stays, so the depths in between lie within the ends
+        if !self.camera.auto_clip_planes {
+            let current = self.camera.state();
+            self.camera.widen_clip_planes(bounds, &current);
+            self.camera.widen_clip_planes(bounds, &state);
+        }
+        self.move_camera_to(state, duration);
+    }
+
+    // the controller continues from where the camera was moved to
+    fn attach_controller(&mut self) {
+        self.camera.stop();
+        if self.orbit.enabled {
+            self.orbit.attach(&self.camera);
+        }
+    }
+
+    fn move_camera_to(&mut self, state: CameraState, duration: f64) {
+        self.camera_path.playing = false;
+        self.camera.stop();
+        self.orbit.stop();
+        if duration > 0. {
+            self.transition = Some(CameraTransition::new(self.camera.state(), state, duration, self.transition_easing));
+        } else {
+            self.transition = None;
+            self.camera.set_state(&state);
+            self.attach_controller();
+        }
+    }
+
+    // kind_id: 1 - directional, 2 - point, 3 - spot; returns -1 for not recognized kind
+    pub fn add_light(&mut self, kind_id: u32) -> i32 {
+        match LightKind::from_id(kind_id) {
+            Some(kind) => self.world.add_light(kind) as i32,
+            None => -1
+        }
+    }
+
+    pub fn remove_light(&mut self, light_handle: usize) {
+        self.world.remove_light(light_handle);
+    }
+
+    pub fn set_light_position(&mut self, light_handle: usize, x: f64, y: f64, z: f64) {
+        self.world.set_light_position(light_handle, x, y, z);
+    }
+
+    pub fn set_light_direction(&mut self, light_handle: usize, x: f64, y: f64, z: f64) {
+        self.world.set_light_direction(light_handle, x, y, z);
+    }
+
+    pub fn set_light_color(&mut self, light_handle: usize, r: f64, g: f64, b: f64) {
+        self.world.set_light_color(light_handle, r, g, b);
+    }
+
+    // 1 - intensity, 2..4 - constant, linear and quadratic attenuation, 5, 6 - spot inner and outer cone angles
+    pub fn set_light_param(&mut self, light_handle: usize, param_id: u32, param_value: f64) {
+        self.world.set_light_param(light_handle, param_id, param_value);
+    }
+
+    // mode_id: 1 - solid color, 2 - vertical gradient, 3 - cubemap, 4 - equirectangular panorama, 5 - environment map
+    pub fn set_background_mode(&mut self, mode_id: u32) {
+        if let Some(mode) = BackgroundMode::from_id(mode_id) {
+            self.world.background.set_mode(mode);
+        }
+    }
+
+    pub fn set_background_color(&mut self, r: u8, g: u8, b: u8) {
+        self.world.background.color = ColorSpace::Srgb.decode_color(&Color { r, g, b });
+    }
+
+    pub fn set_background_gradient(&mut self, top_r: u8, top_g: u8, top_b: u8, bottom_r: u8, bottom_g: u8, bottom_b: u8) {
+        self.world.background.top_color = ColorSpace::Srgb.decode_color(&Color { r: top_r, g: top_g, b: top_b });
+        self.world.background.bottom_color = ColorSpace::Srgb.decode_color(&Color { r: bottom_r, g: bottom_g, b: bottom_b });
+    }
+
+    // texture_index: 1..6 - cubemap faces (+x, -x, +y, -y, +z, -z), 7 - panorama
+    pub fn set_background_texture_size(&mut self, texture_index: usize, width: usize, height: usize) {
+        self.world.background.set_texture_size(texture_index, width, height);
+    }
+
+    pub fn get_background_texture_pixels(&mut self, texture_index: usize) -> *const Pixel {
+        self.world.background.get_texture_pixels(texture_index)
+    }
+
+    // size in bytes of the radiance .hdr file to be written to the environment data
+    pub fn set_environment_data_size(&mut self, size: usize) {
+        self.world.environment.source.resize(size, 0);
+    }
+
+    pub fn get_environment_data(&mut self) -> *const u8 {
+        self.world.environment.source.as_ptr()
+    }
+
+    // parses the written .hdr file and precomputes the lighting from it
+    pub fn load_environment(&mut self) -> bool {
+        self.world.environment.load()
+    }
+
+    // 1 - enabled (0 / 1), 2 - intensity, 3 - rotation around the vertical axis in radians
+    pub fn set_environment_param(&mut self, param_id: u32, param_value: f64) {
+        self.world.environment.set_param(param_id, param_value);
+    }
+
+    // operator_id: 0 - none, 1 - reinhard, 2 - extended reinhard, 3 - aces filmic, 4 - agx
+    pub fn set_tone_mapping(&mut self, operator_id: u32) {
+        if let Some(operator) = ToneMapping::from_id(operator_id) {
+            self.tone_mapper.operator = operator;
+        }
+    }
+
+    // mode_id: 0 - none, 1 - linear, 2 - exponential, 3 - exponential squared
+    pub fn set_fog_mode(&mut self, mode_id: u32) {
+        if let Some(mode) = FogMode::from_id(mode_id) {
+            self.world.fog.mode = mode;
+        }
+    }
+
+    pub fn set_fog_color(&mut self, r: u8, g: u8, b: u8) {
+        self.world.fog.color = ColorSpace::Srgb.decode_color(&Color { r, g, b });
+    }
+
+    // 1 - density, 2 - linear start, 3 - linear end, 4 - height fog density (0 - off), 5 - height falloff, 6 - base height
+    pub fn set_fog_param(&mut self, param_id: u32, param_value: f64) {
+        self.world.fog.set_param(param_id, param_value);
+    }
+
+    // 1 - exposure, 2 - gamma (used without the srgb output), 3 - white point of the extended reinhard,
+    // 4 - srgb output (0 / 1)
+    pub fn set_tone_mapping_param(&mut self, param_id: u32, param_value: f64) {
+        self.tone_mapper.set_param(param_id, param_value);
+    }
+
+    // 1 - enabled (0 / 1), 2 - view space radius, 3 - strength, 4 - sample count, 5 - depth bias
+    pub fn set_ssao_param(&mut self, param_id: u32, param_value: f64) {
+        self.ssao.set_param(param_id, param_value);
+    }
+
+    // 1 - enabled (0 / 1), 2 - max blur radius in pixels, 3 - sample count; the lens is set on the camera
+    pub fn set_depth_of_field_param(&mut self, param_id: u32, param_value: f64) {
+        self.depth_of_field.set_param(param_id, param_value);
+    }
+
+    // 1 - enabled (0 / 1), 2 - threshold, 3 - radius in texels of the pyramid levels, 4 - intensity
+    pub fn set_bloom_param(&mut self, param_id: u32, param_value: f64) {
+        self.bloom.set_param(param_id, param_value);
+    }
+
+    pub fn select_object(&mut self, object_handle: usize, selected: bool) {
+        self.selection.select_object(object_handle, selected);
+    }
+
+    pub fn select_face(&mut self, object_handle: usize, face_index: usize, selected: bool) {
+        self.selection.select_face(object_handle, face_index, selected);
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection.clear();
+    }
+
+    pub fn set_selection_outline_color(&mut self, r: u8, g: u8, b: u8) {
+        self.selection.outline_color = Color { r, g, b };
+    }
+
+    pub fn set_selection_tint_color(&mut self, r: u8, g: u8, b: u8) {
+        self.selection.tint_color = Color { r, g, b };
+    }
+
+    // 1 - outline width in pixels, 2 - tint strength [0:1], 3 - x-ray outline of the occluded parts (0 / 1), 4 - dash length
+    pub fn set_selection_param(&mut self, param_id: u32, param_value: f64) {
+        self.selection.set_param(param_id, param_value);
+    }
+
+    // view_id: 0 - lit scene, 1 - unlit albedo, 2 - face normals, 3 - interpolated normals, 4 - normal map normals,
+    // 5 - uv, 6 - depth, 7 - object id, 8 - face id, 9 - overdraw, 10 - lighting only (white albedo)
+    pub fn set_debug_view(&mut self, view_id: u32) {
+        if let Some(view) = DebugView::from_id(view_id) {
+            self.debug_view = view;
+        }
+    }
 
+    // 1 - enabled (0 / 1), 2 - depth bias, 3 - pcf radius in texels, 4 - shadow map size
+    pub fn set_shadow_param(&mut self, param_id: u32, param_value: f64) {
+        self.shadow_map.set_param(param_id, param_value);
+    }
+
+    // the object under the pixel of the last computed frame, undefined for the background
+    pub fn pick(&self, x: u32, y: u32) -> Option<PickResult> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let pixel_index = raster::get_index(y, x, self.width);
+        if self.z_buf[pixel_index] == 1. || self.object_index_buffer[pixel_index] < 0 {
+            return None;
+        }
+        let object_handle = self.object_index_buffer[pixel_index] as usize;
+        let face_index = self.face_index_buffer[pixel_index] as usize;
+        let obj = &self.world.objects[object_handle];
+        let face = &obj.faces[face_index];
+
+        // the face is transformed again the same way compute does it
+        let to_world = obj.get_to_world_matrix();
+        let final_matrix = self.to_screen_matrix * self.camera.projection_matrix * self.camera.look_at_matrix * to_world;
+        let mut view_vertices = [Vertex::zeros(); 3];
+        let mut linear_z = [0.; 3];
+        for i in 0..3 {
+            let v = final_matrix * obj.vertices[face.vertices_indexes[i]];
+            linear_z[i] = v[3];
+            view_vertices[i] = v / v[3];
+        }
+
+        let view_point = Vertex::new(x as f64, y as f64, self.z_buf[pixel_index], 1.);
+        let barycentric = raster::calc_barycentric(&view_point, &view_vertices[0], &view_vertices[1], &view_vertices[2]);
+        let interpolate = |attributes: [&Vector4<f64>; 3]| raster::lerp(
+            &barycentric, attributes[0], attributes[1], attributes[2], linear_z[0], linear_z[1], linear_z[2]
+        );
+
+        let uv = interpolate([
+            &obj.texture_vertices[face.texture_vertices_indexes[0]],
+            &obj.texture_vertices[face.texture_vertices_indexes[1]],
+            &obj.texture_vertices[face.texture_vertices_indexes[2]]
+        ]);
+        let world_point = to_world * interpolate([
+            &obj.vertices[face.vertices_indexes[0]],
+            &obj.vertices[face.vertices_indexes[1]],
+            &obj.vertices[face.vertices_indexes[2]]
+        ]);
+        let normal = if obj.use_normal_texture {
+            obj.normal_texture_normals.sample(uv[0], uv[1], 0.).normalize()
+        } else {
+            interpolate([
+                &obj.vertices_normals[face.vertices_normals_indexes[0]],
+                &obj.vertices_normals[face.vertices_normals_indexes[1]],
+                &obj.vertices_normals[face.vertices_normals_indexes[2]]
+            ])
+        };
+        let world_normal = to_world.try_inverse().unwrap_or_else(Matrix4::identity).transpose() * normal;
+        let world_normal = Vector3::new(world_normal[0], world_normal[1], world_normal[2]).normalize();
+
+        Some(PickResult {
+            object_handle,
+            face_index,
+            barycentric_x: barycentric.x,
+            barycentric_y: barycentric.y,
+            barycentric_z: barycentric.z,
+            u: uv[0],
+            v: uv[1],
+            x: world_point[0],
+            y: world_point[1],
+            z: world_point[2],
+            normal_x: world_normal[0],
+            normal_y: world_normal[1],
+            normal_z: world_normal[2],
+            depth: self.camera.linear_depth(self.z_buf[pixel_index])
+        })
+    }
+
+    // nearest face hit by the world space ray, visible on the screen or not
+    pub fn raycast(&self, origin_x: f64, origin_y: f64, origin_z: f64, direction_x: f64, direction_y: f64, direction_z: f64) -> Option<RayHit> {
+        let origin = Point3::new(origin_x, origin_y, origin_z);
+        let direction = Vector3::new(direction_x, direction_y, direction_z);
+        self.world.raycast(&origin, &direction).map(|(object_handle, hit)| RayHit {
+            object_handle,
+            face_index: hit.face_index,
+            distance: hit.distance,
+            barycentric_x: hit.barycentric.x,
+            barycentric_y: hit.barycentric.y,
+            barycentric_z: hit.barycentric.z
+        })
     }
 
     fn clear_image(&mut self) {
@@ -179,25 +737,39 @@ impl Image {
         //     pixel.color.b = 0;
         //     pixel.a = 255;
         // }
-        // for pixel in self.emission_buf.iter_mut() {
-        //     pixel.color.r = 0;
-        //     pixel.color.g = 0;
-        //     pixel.color.b = 0;
-        //     pixel.a = 255;
-        // }
 
         for z in self.z_buf.iter_mut() {
             *z = 1.;
         }
-        // for i in self.face_index_buffer.iter_mut() {
-        //     *i = -1;
-        // }
-        // for i in self.object_index_buffer.iter_mut() {
-        //     *i = -1;
-        // }
+        // the selection and the debug views read the index buffers of the empty pixels too
+        for i in self.face_index_buffer.iter_mut() {
+            *i = -1;
+        }
+        for i in self.object_index_buffer.iter_mut() {
+            *i = -1;
+        }
     }
 
 
+    // the depth view holds the linear depth, it is scaled so the nearest pixel is white and the farthest is black
+    fn normalize_depth_view(&mut self) {
+        let mut near = f32::MAX;
+        let mut far = f32::MIN;
+        for (color, z) in self.color_buf.iter().zip(self.z_buf.iter()) {
+            if *z != 1. {
+                near = near.min(color.r);
+                far = far.max(color.r);
+            }
+        }
+        let range = (far - near).max(1e-6);
+        for (color, z) in self.color_buf.iter_mut().zip(self.z_buf.iter()) {
+            if *z != 1. {
+                let value = 1. - (color.r - near) / range;
+                *color = Color { r: value, g: value, b: value };
+            }
+        }
+    }
+
     fn is_faced_towards_viewer(&v1: &Vector4<f64>, &v2: &Vector4<f64>, &v3: &Vector4<f64>) -> bool {
         (v2[0] - v1[0]) * (v3[1] - v1[1]) - (v3[0] - v1[0]) * (v2[1] - v1[1]) < 0.
     }
@@ -212,7 +784,12 @@ impl Image {
         set_panic_hook();
         self.clear_image();
 
-        self.camera.tick();
+        if self.camera_path.playing {
+            self.camera_path.place(&mut self.camera);
+        }
+        if self.camera.auto_clip_planes {
+            self.camera.fit_clip_planes(&self.world.bounds());
+        }
 
         let look_at = self.camera.look_at_matrix;
 
@@ -225,21 +802,22 @@ impl Image {
         let view_box_1 = to_screen * Vertex::new(-1., 1., 0., 1.);
         let view_box_2 = to_screen * Vertex::new(1., -1., 1., 1.);
 
+        let mut object_to_world_translations = Vec::new();
         let mut world_to_object_translations = Vec::new();
-        let mut cameras_in_object_space = Vec::new();
-        let mut lights_in_object_space = Vec::new();
 
         // translating all the vertices into the final space (camera space)
         let mut vertices_linear_z: Vec<Vec<f64>> = Vec::new();
         let view_vertices: Vec<Vec<Vertex>> = self.world.objects.iter_mut().map(|obj|{
-            let to_world = obj.translation_matrix * obj.scale_matrix * obj.rotation_matrix;
+            let to_world = obj.get_to_world_matrix();
+            object_to_world_translations.push(to_world);
             world_to_object_translations.push(to_world.try_inverse().unwrap());
 
             let final_matrix = object_independent_matrix * to_world;
             let mut object_vertices_linear_z: Vec<f64> = Vec::new();
             let view_vertices: Vec<Vertex> = obj.vertices.iter().map(|vertex| {
                 let v = final_matrix * *vertex;
-                object_vertices_linear_z.push(v[2]);
+                // the perspective divisor, attributes are interpolated with it; 1 for the orthographic views
+                object_vertices_linear_z.push(v[3]);
                 v / v[3]
             }).collect();
 
@@ -252,11 +830,11 @@ impl Image {
         }).collect();
 
 
+        let debug_view = self.debug_view;
+        let mut overdraw = if debug_view == DebugView::Overdraw { vec![0u32; self.z_buf.len()] } else { Vec::new() };
+
         // pre-run (not calculating the light and colors)
         for (object_index, obj) in self.world.objects.iter_mut().enumerate() {
-
-            cameras_in_object_space.push(world_to_object_translations[object_index] * self.camera.position.to_homogeneous());
-            lights_in_object_space.push(world_to_object_translations[object_index] * self.world.direct_light_direction);
             for (face_index, face) in obj.faces.iter().enumerate() {
                 let i0 = face.vertices_indexes[0] as usize;
                 let i1 = face.vertices_indexes[1] as usize;
@@ -284,18 +862,65 @@ impl Image {
                     is_partial
                 );
 
+                if debug_view == DebugView::Overdraw {
+                    let vertices = &view_vertices[object_index];
+                    raster::rasterize_face(
+                        self.width as i32, self.height as i32, &vertices[i0], &vertices[i1], &vertices[i2], is_partial,
+                        |index, _| overdraw[index] += 1
+                    );
+                }
+            }
+        }
+
+        let shadow_light_handle = self.world.get_shadow_light_handle();
+        if self.shadow_map.enabled {
+            if let Some(Some(light)) = shadow_light_handle.map(|handle| &self.world.lights[handle]) {
+                let light_direction = light.direction;
+                self.shadow_map.render(&self.world.objects, &light_direction);
             }
         }
 
-        let mut emission_buf_used = false;
+        let shading_context = ShadingContext {
+            lights: &self.world.lights,
+            shadow_map: &self.shadow_map,
+            shadow_light_handle: if self.shadow_map.enabled { shadow_light_handle } else { None },
+            environment: &self.world.environment,
+            ambient_light_color: self.world.background_light_color
+        };
+
+        let background = &self.world.background;
+        let is_background_directional = background.mode.is_directional();
+        let background_pixel_angle = self.camera.pixel_angle(self.height as f64);
 
         for y in 0..self.height {
             for x in 0..self.width {
                 let pixel_index = raster::get_index(y, x, self.width);
 
+                if debug_view == DebugView::Overdraw {
+                    // 8 layers are shown as the hottest
+                    self.color_buf[pixel_index] = debug::heat_color(overdraw[pixel_index] as f64 / 8.).to_f32();
+                    continue;
+                }
+
+                if self.z_buf[pixel_index] == 1. && debug_view.is_unlit() {
+                    self.color_buf[pixel_index] = Color { r: 0., g: 0., b: 0. };
+                    continue;
+                }
+
                 if self.z_buf[pixel_index] == 1. {
-                    self.pixels[pixel_index].color = white_color;
-                    self.emission_buf[pixel_index].color = black_color;
+                    let view_direction = if is_background_directional {
+                        self.camera.get_view_direction(
+                            x as f64 / self.width as f64 * 2. - 1.,
+                            1. - y as f64 / self.height as f64 * 2.
+                        )
+                    } else {
+                        self.camera.front
+                    };
+                    let color = background.get_color(
+                        y as f64 / self.height as f64, &view_direction, background_pixel_angle, &self.world.environment
+                    );
+                    self.color_buf[pixel_index] = color.to_f32();
+                    self.ambient_buf[pixel_index] = Color { r: 0., g: 0., b: 0. };
                     continue;
                 }
 
@@ -337,9 +962,6 @@ impl Image {
                 let vt2 = &obj.texture_vertices[it2];
                 let vt3 = &obj.texture_vertices[it3];
 
-                let direct_light_direction = &lights_in_object_space[object_index];
-                let camera_position = &cameras_in_object_space[object_index];
-
                 let view_point = Vertex::new(x as f64, y as f64, self.z_buf[pixel_index], 1.);
 
                 let barycentric = raster::calc_barycentric(&view_point, view_v1, view_v2, view_v3);
@@ -347,14 +969,19 @@ impl Image {
                 // let texture_pixel_vertex = vt1 * barycentric.x + vt2 * barycentric.y + vt3 * barycentric.z;
                 let texture_pixel_vertex = raster::lerp(&barycentric, vt1, vt2, vt3, view_v1_linear_z, view_v2_linear_z, view_v3_linear_z);
 
+                // texture coordinates of the neighbour pixels to choose the mip level
+                let barycentric_dx = raster::calc_screen_barycentric(x as f64 + 1., y as f64, view_v1, view_v2, view_v3);
+                let barycentric_dy = raster::calc_screen_barycentric(x as f64, y as f64 + 1., view_v1, view_v2, view_v3);
+                let uv_dx = raster::lerp(&barycentric_dx, vt1, vt2, vt3, view_v1_linear_z, view_v2_linear_z, view_v3_linear_z) - texture_pixel_vertex;
+                let uv_dy = raster::lerp(&barycentric_dy, vt1, vt2, vt3, view_v1_linear_z, view_v2_linear_z, view_v3_linear_z) - texture_pixel_vertex;
+                let (u, v) = (texture_pixel_vertex[0], texture_pixel_vertex[1]);
+                let (du_dx, dv_dx, du_dy, dv_dy) = (uv_dx[0], uv_dx[1], uv_dy[0], uv_dy[1]);
+
                 let diffuse_texture_color: Color<f64>;
-                if obj.use_diffuse_texture {
-                    let diffuse_texture_color_u8 = obj.diffuse_texture.get_pixel(texture_pixel_vertex[0], texture_pixel_vertex[1]).color;
-                    diffuse_texture_color = Color {
-                        r: diffuse_texture_color_u8.r as f64 / 255.,
-                        g: diffuse_texture_color_u8.g as f64 / 255.,
-                        b: diffuse_texture_color_u8.b as f64 / 255.,
-                    };
+                if debug_view == DebugView::LightingOnly {
+                    diffuse_texture_color = Color { r: 1., g: 1., b: 1. };
+                } else if obj.use_diffuse_texture {
+                    diffuse_texture_color = obj.diffuse_texture_color.sample_grad(u, v, du_dx, dv_dx, du_dy, dv_dy);
                 } else {
                     diffuse_texture_color = Color {
                         r: obj.model_color.r,
@@ -363,128 +990,281 @@ impl Image {
                     }
                 }
 
-                if obj.use_emission_texture {
-                    self.emission_buf[pixel_index].color = obj.emission_texture.get_pixel(texture_pixel_vertex[0], texture_pixel_vertex[1]).color;
-                    emission_buf_used = true;
+                let emission = if obj.use_emission_texture {
+                    obj.emission_texture_color.sample_grad(u, v, du_dx, dv_dx, du_dy, dv_dy) * EMISSION_STRENGTH
                 } else {
-                    self.emission_buf[pixel_index].color = black_color;
-                }
+                    Color { r: 0., g: 0., b: 0. }
+                };
 
                 let normal;
                 if obj.use_normal_texture {
-                    normal = *obj.normal_texture_normals.get_pixel(texture_pixel_vertex[0], texture_pixel_vertex[1]);
+                    normal = obj.normal_texture_normals.sample_grad(u, v, du_dx, dv_dx, du_dy, dv_dy).normalize();
                 } else {
                     // normal = vn1 * barycentric.x + vn2 * barycentric.y + vn3 * barycentric.z;
                     normal = raster::lerp(&barycentric, vn1, vn2, vn3, view_v1_linear_z, view_v2_linear_z, view_v3_linear_z);
                 }
 
-                let cos = normal.normalize().dot(&(-direct_light_direction.normalize()));
-
                 // let model_point = model_v1 * barycentric.x + model_v2 * barycentric.y + model_v3 * barycentric.z;
                 let model_point = raster::lerp(&barycentric, model_v1, model_v2, model_v3, view_v1_linear_z, view_v2_linear_z, view_v3_linear_z);
 
-                let model_camera_direction = camera_position - model_point;
-
-                let reflection_direction = direct_light_direction - 2. * (direct_light_direction).dot(&normal) * normal;
-
-                let mut gloss_not_powered = reflection_direction.normalize().dot(&model_camera_direction.normalize());
-                if gloss_not_powered < 0. {
-                    gloss_not_powered = 0.;
+                // lighting is calculated in the world space
+                let world_point = object_to_world_translations[object_index] * model_point;
+                let world_point = Point3::new(world_point[0], world_point[1], world_point[2]);
+                let world_normal = world_to_object_translations[object_index].transpose() * normal;
+                let world_normal = Vector3::new(world_normal[0], world_normal[1], world_normal[2]).normalize();
+                let camera_direction = (self.camera.ray_origin(&world_point) - world_point).normalize();
+
+                if debug_view.is_unlit() {
+                    let to_world_normal = |normal: &Vector4<f64>| {
+                        let world_normal = world_to_object_translations[object_index].transpose() * normal;
+                        Vector3::new(world_normal[0], world_normal[1], world_normal[2]).normalize()
+                    };
+                    let color = match debug_view {
+                        DebugView::Albedo => Color {
+                            r: linear_to_srgb(diffuse_texture_color.r),
+                            g: linear_to_srgb(diffuse_texture_color.g),
+                            b: linear_to_srgb(diffuse_texture_color.b)
+                        },
+                        DebugView::FaceNormals => debug::normal_color(&to_world_normal(&face.normal)),
+                        DebugView::InterpolatedNormals => debug::normal_color(&to_world_normal(
+                            &raster::lerp(&barycentric, vn1, vn2, vn3, view_v1_linear_z, view_v2_linear_z, view_v3_linear_z)
+                        )),
+                        DebugView::NormalMapNormals => debug::normal_color(&world_normal),
+                        DebugView::Uv => Color { r: u - u.floor(), g: v - v.floor(), b: 0. },
+                        // normalized over the visible range after the pass
+                        DebugView::Depth => {
+                            let linear_z = self.camera.linear_depth(self.z_buf[pixel_index]);
+                            Color { r: linear_z, g: linear_z, b: linear_z }
+                        },
+                        DebugView::ObjectId => debug::id_color(object_index),
+                        DebugView::FaceId => debug::id_color(face_index),
+                        _ => Color { r: 0., g: 0., b: 0. }
+                    };
+                    self.color_buf[pixel_index] = color.to_f32();
+                    continue;
                 }
-                let gloss = gloss_not_powered.powf(obj.shininess);
 
-                let specular: &Color<f64>;
+                let specular: Color<f64>;
                 if obj.use_specular_texture {
-                    specular = obj.specular_texture_coeff.get_pixel(texture_pixel_vertex[0], texture_pixel_vertex[1])
+                    specular = obj.specular_texture_coeff.sample_grad(u, v, du_dx, dv_dx, du_dy, dv_dy)
                 } else {
-                    specular = &obj.specular
+                    specular = obj.specular
                 }
-                
 
-                let obj = &self.world.objects[object_index];
-                let bg_color = &self.world.background_light_color;
-                let dl_color = &self.world.direct_light_color;
+                let metallic = if obj.use_metallic_texture {
+                    obj.metallic_texture_coeff.sample_grad(u, v, du_dx, dv_dx, du_dy, dv_dy)
+                } else {
+                    obj.metallic
+                };
+                let roughness = if obj.use_roughness_texture {
+                    obj.roughness_texture_coeff.sample_grad(u, v, du_dx, dv_dx, du_dy, dv_dy)
+                } else {
+                    obj.roughness
+                };
+                let ambient_occlusion = if obj.use_ao_texture {
+                    obj.ao_texture_coeff.sample_grad(u, v, du_dx, dv_dx, du_dy, dv_dy)
+                } else {
+                    1.
+                };
 
-                
-                self.pixels[pixel_index].color.r = ((
-                    bg_color.r * obj.ambient.r +
-                    dl_color.r * specular.r * obj.specular_intensity.r * gloss +
-                    diffuse_texture_color.r * obj.diffuse_intensity.r * cos
-                ) * 255.) as u8;
-
-                self.pixels[pixel_index].color.g = ((
-                    bg_color.g * obj.ambient.g +
-                    dl_color.g * specular.g * obj.specular_intensity.g * gloss +
-                    diffuse_texture_color.g * obj.diffuse_intensity.g * cos
-                ) * 255.) as u8;
-
-                self.pixels[pixel_index].color.b = ((
-                    bg_color.b * obj.ambient.b +
-                    dl_color.b * specular.b * obj.specular_intensity.b * gloss +
-                    diffuse_texture_color.b * obj.diffuse_intensity.b * cos
-                ) * 255.) as u8;
+                let fragment = Fragment {
+                    world_point,
+                    world_normal,
+                    camera_direction,
+                    uv: Vector2::new(u, v),
+                    uv_dx: Vector2::new(du_dx, dv_dx),
+                    uv_dy: Vector2::new(du_dy, dv_dy)
+                };
+                let surface = Surface {
+                    albedo: diffuse_texture_color,
+                    specular,
+                    metallic,
+                    roughness,
+                    ambient_occlusion,
+                    emission
+                };
+                let shaded = obj.shader.fragment(&fragment, &surface, obj, &shading_context);
+                let (direct_light, ambient_light) = (shaded.direct, shaded.ambient);
+
+                // the ambient part is added after the occlusion pass, so the fog is applied to both parts separately
+                let fog = &self.world.fog;
+                let (direct_light, ambient_light) = if fog.is_enabled() {
+                    let linear_z = self.camera.linear_depth(self.z_buf[pixel_index]);
+                    let transmittance = fog.transmittance(linear_z, &self.camera.ray_origin(&world_point), &world_point);
+                    (
+                        direct_light * transmittance + fog.color * (1. - transmittance),
+                        ambient_light * transmittance
+                    )
+                } else {
+                    (direct_light, ambient_light)
+                };
 
+                self.color_buf[pixel_index] = direct_light.to_f32();
+                self.ambient_buf[pixel_index] = ambient_light.to_f32();
+                self.normal_buf[pixel_index] = look_at.fixed_view::<3, 3>(0, 0) * world_normal;
             }
         }
 
-        if emission_buf_used {
-            // bluring emission buf (gaussian blur)
-            for i in 0..5 {
-                //horizontal
-                for x in 0..self.width {
-                    for y in 0..self.height {
-                        let pixel_index = raster::get_index(y, x, self.width);
-                        let mut pixel_color = self.emission_buf[pixel_index].color.to_f64() * blur_weights[0];
-                        for offset in 1..5 as u32 {
-                            let coeff = blur_weights[offset as usize];
-                            if x as i32 - offset as i32 >= 0 {
-                                let index_l = raster::get_index(y, x - offset, self.width);
-                                pixel_color = pixel_color + (self.emission_buf[index_l].color.to_f64() * coeff);
-                            }
-                            if x as i32 + (offset as i32) < self.width as i32 {
-                                let index_r = raster::get_index(y, x + offset, self.width);
-                                pixel_color = pixel_color + (self.emission_buf[index_r].color.to_f64() * coeff);
-                            }
-                        }
-                        self.pingpong_emission_buf[pixel_index].color = pixel_color.to_u8();
-                    }
-                }
+        if debug_view.is_unlit() {
+            if debug_view == DebugView::Depth {
+                self.normalize_depth_view();
+            }
+            for i in 0..(self.width * self.height) as usize {
+                self.pixels[i].color = (self.color_buf[i].to_f64() * 255.).to_u8();
+            }
+        } else {
+            if self.ssao.enabled {
+                self.ssao.compute(&self.z_buf, &self.normal_buf, self.width as usize, self.height as usize, &(to_screen * projection));
+            }
+            for i in 0..(self.width * self.height) as usize {
+                let visibility = if self.ssao.enabled { self.ssao.visibility(i) } else { 1. };
+                self.color_buf[i] = (self.color_buf[i].to_f64() + self.ambient_buf[i].to_f64() * visibility).to_f32();
+            }
 
-                //vertical
-                for x in 0..self.width {
-                    for y in 0..self.height {
-                        let pixel_index = raster::get_index(y, x, self.width);
-                        let mut pixel_color = self.pingpong_emission_buf[pixel_index].color.to_f64() * blur_weights[0];
-                        for offset in 1..5 as u32 {
-                            let coeff = blur_weights[offset as usize];
-                            if y as i32 - offset as i32 >= 0 {
-                                let index_l = raster::get_index(y - offset, x, self.width);
-                                pixel_color = pixel_color + (self.pingpong_emission_buf[index_l].color.to_f64() * coeff);
-                            }
-                            if y as i32 + (offset as i32) < self.height as i32 {
-                                let index_r = raster::get_index(y + offset, x, self.width);
-                                pixel_color = pixel_color + (self.pingpong_emission_buf[index_r].color.to_f64() * coeff);
-                            }
-                        }
-                        self.emission_buf[pixel_index].color = pixel_color.to_u8();
+            if self.depth_of_field.enabled {
+                let (width, height) = (self.width as usize, self.height as usize);
+                if self.camera.autofocus {
+                    if let Some(depth) = DepthOfField::center_depth(&self.z_buf, width, height, &(to_screen * projection)) {
+                        self.camera.focus_distance = depth;
                     }
                 }
+                self.depth_of_field.apply(&mut self.color_buf, &self.z_buf, width, height, &(to_screen * projection), &self.camera);
             }
-            let gamma = 2.2;
-            let exposure = 1.;
-            // combining emission with image
+
+            self.bloom.apply(&mut self.color_buf, self.width as usize, self.height as usize);
+
             for i in 0..(self.width * self.height) as usize {
-                let pixel_color = self.pixels[i].color.to_f64();
-                let emission_color = self.emission_buf[i].color.to_f64();
-                let color = pixel_color + emission_color * 1.5;
-                let final_color = Color{
-                    r: (1. - ((-color.r / 255.) * exposure).exp()).powf(1./ gamma) * 255.,
-                    g: (1. - ((-color.g / 255.) * exposure).exp()).powf(1./ gamma) * 255.,
-                    b: (1. - ((-color.b / 255.) * exposure).exp()).powf(1./ gamma) * 255.,
-                };
-                self.pixels[i].color = final_color.to_u8();
+                self.pixels[i].color = self.tone_mapper.apply(self.color_buf[i].to_f64());
             }
+        }
 
+        if !self.selection.is_empty() {
+            self.selection.build_mask(
+                self.width as usize, self.height as usize, &self.world.objects, &view_vertices,
+                &self.object_index_buffer, &self.face_index_buffer
+            );
+            self.selection.apply(&mut self.pixels, self.width as usize, self.height as usize);
         }
     }
-}
\ No newline at end of file
+}
+
+// the part of the api available only from rust
+impl Image {
+    pub fn set_object_shader(&mut self, object_handle: usize, shader: Rc<dyn Shader>) {
+        self.world.set_object_shader(object_handle, shader);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shader::Shaded;
+    use crate::object::Object;
+
+    // horizontal square facing up
+    fn add_square(image: &mut Image, x: f64, y: f64, z: f64, half_size: f64) {
+        let handle = image.new_object() as usize;
+        for (dx, dz) in [(-1., -1.), (-1., 1.), (1., 1.), (1., -1.)] {
+            image.add_object_vertex(handle, x + dx * half_size, y, z + dz * half_size);
+        }
+        image.add_object_texture_vertex(handle, 0., 0., 0.);
+        image.add_object_vertex_normal(handle, 0., 1., 0.);
+        image.add_object_face(handle, 0, 0, 0, 1, 0, 0, 2, 0, 0);
+        image.add_object_face(handle, 0, 0, 0, 2, 0, 0, 3, 0, 0);
+    }
+
+    // brightness of the pixel showing the world point
+    fn brightness_at(image: &Image, point: &Point3<f64>) -> u32 {
+        let v = image.to_screen_matrix * image.camera.projection_matrix * image.camera.look_at_matrix * point.to_homogeneous();
+        let (x, y) = ((v[0] / v[3]) as u32, (v[1] / v[3]) as u32);
+        assert!(x < image.width && y < image.height);
+        let color = &image.pixels[raster::get_index(y, x, image.width)].color;
+        color.r as u32 + color.g as u32 + color.b as u32
+    }
+
+    #[test]
+    fn blocker_shadows_floor() {
+        let mut image = Image::new(64, 48);
+        add_square(&mut image, 0., 0., 0., 2.);
+        // the light comes along (-1, -1, -1), the shadow falls around (0, 0, -1)
+        add_square(&mut image, 0.5, 0.5, -0.5, 0.25);
+        let (shadowed, lit) = (Point3::new(0., 0., -1.), Point3::new(0.5, 0., 0.5));
+
+        image.compute();
+        let (shadowed_without, lit_without) = (brightness_at(&image, &shadowed), brightness_at(&image, &lit));
+
+        image.set_shadow_param(1, 1.);
+        image.compute();
+        assert!(brightness_at(&image, &shadowed) < shadowed_without);
+        assert_eq!(brightness_at(&image, &lit), lit_without);
+    }
+
+    #[test]
+    fn autofocus_on_center_object() {
+        let mut image = Image::new(64, 48);
+        add_square(&mut image, 0., 0., 0., 2.);
+        image.set_depth_of_field_param(1, 1.);
+        image.set_camera_param(24, 1.);
+        image.compute();
+        // the camera looks at the origin from (1.5, 1.5, 1.5)
+        assert!((image.camera.focus_distance - 1.5 * 3_f64.sqrt()).abs() < 0.05);
+    }
+
+    #[test]
+    fn moved_selection_leaves_background_clean() {
+        let mut image = Image::new(64, 48);
+        add_square(&mut image, 0., 0., 0., 0.5);
+        image.select_object(0, true);
+        // the stale indices would tint the pixels the object covered
+        image.set_selection_param(2, 0.5);
+        image.compute();
+        let covered: Vec<usize> = (0..image.object_index_buffer.len()).filter(|i| image.object_index_buffer[*i] == 0).collect();
+        assert!(!covered.is_empty());
+
+        image.set_object_translaiton(0, 100., 0., 0.);
+        image.compute();
+        for i in covered {
+            let color = &image.pixels[i].color;
+            assert_eq!((color.r, color.g, color.b), (255, 255, 255));
+        }
+    }
+
+    struct RedShader;
+
+    impl Shader for RedShader {
+        fn fragment(&self, _: &Fragment, _: &Surface, _: &Object, _: &ShadingContext) -> Shaded {
+            Shaded { direct: Color { r: 1., g: 0., b: 0. }, ambient: Color { r: 0., g: 0., b: 0. } }
+        }
+    }
+
+    #[test]
+    fn custom_shader_colors_object() {
+        let mut image = Image::new(64, 48);
+        add_square(&mut image, 0., 0., 0., 2.);
+        image.set_object_shader(0, Rc::new(RedShader));
+        image.compute();
+        let color = &image.pixels[raster::get_index(image.height / 2, image.width / 2, image.width)].color;
+        assert!(color.r > 0);
+        assert_eq!((color.g, color.b), (0, 0));
+    }
+
+    #[test]
+    fn framed_large_model_is_inside_clip_planes() {
+        let mut image = Image::new(64, 48);
+        add_square(&mut image, 0., 0., 0., 2.);
+        image.set_object_scale(0, 20.);
+        image.frame_all(0.);
+        image.compute();
+        assert_eq!(image.pick(32, 24).map(|hit| hit.object_handle), Some(0));
+
+        // on the way of the transition too
+        image.set_object_scale(0, 400.);
+        image.frame_all(1.);
+        image.update(0.5);
+        image.compute();
+        assert_eq!(image.pick(32, 24).map(|hit| hit.object_handle), Some(0));
+        image.update(1.);
+        image.compute();
+        assert_eq!(image.pick(32, 24).map(|hit| hit.object_handle), Some(0));
+    }
+}
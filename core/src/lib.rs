@@ -9,4 +9,26 @@ mod camera;
 mod world;
 mod image;
 mod raster;
-mod texture;
\ No newline at end of file
+mod texture;
+mod shadow;
+mod light;
+mod brdf;
+mod environment;
+mod background;
+mod tonemap;
+mod bloom;
+mod ssao;
+mod dof;
+mod fog;
+mod selection;
+mod debug;
+mod pick;
+mod bvh;
+mod bounds;
+mod orbit;
+mod transition;
+mod path;
+
+// extension points for the rust users of the crate
+pub mod shader;
+pub use image::Image;
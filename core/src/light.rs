@@ -0,0 +1,101 @@
+use crate::types::*;
+use crate::console::log;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum LightKind {
+    Directional,
+    Point,
+    Spot
+}
+
+impl LightKind {
+    pub fn from_id(kind_id: u32) -> Option<LightKind> {
+        match kind_id {
+            1 => Some(LightKind::Directional),
+            2 => Some(LightKind::Point),
+            3 => Some(LightKind::Spot),
+            _ => None
+        }
+    }
+}
+
+pub struct Light {
+    pub kind: LightKind,
+    // point and spot lights
+    pub position: Point3<f64>,
+    // directional and spot lights, direction the light travels in
+    pub direction: Vector3<f64>,
+    pub color: Color<f64>,
+    pub intensity: f64,
+    // distance attenuation: 1 / (constant + linear * d + quadratic * d^2)
+    pub attenuation_constant: f64,
+    pub attenuation_linear: f64,
+    pub attenuation_quadratic: f64,
+    // spot cone half-angles in radians, the light fades out between inner and outer
+    pub inner_cone: f64,
+    pub outer_cone: f64
+}
+
+impl Light {
+    pub fn new(kind: LightKind) -> Light {
+        Light {
+            kind,
+            position: Point3::new(0., 2., 0.),
+            direction: Vector3::new(-1., -1., -1.).normalize(),
+            color: Color{ r: 1., g: 1., b: 1. },
+            intensity: 1.,
+            attenuation_constant: 1.,
+            attenuation_linear: 0.09,
+            attenuation_quadratic: 0.032,
+            inner_cone: std::f64::consts::PI / 12.,
+            outer_cone: std::f64::consts::PI / 9.
+        }
+    }
+
+    pub fn set_param(&mut self, param_id: u32, param_value: f64) {
+        match param_id {
+            1 => self.intensity = param_value,
+            2 => self.attenuation_constant = param_value,
+            3 => self.attenuation_linear = param_value,
+            4 => self.attenuation_quadratic = param_value,
+            5 => self.inner_cone = param_value,
+            6 => self.outer_cone = param_value,
+            _ => log("Not recognized light param")
+        }
+    }
+
+    // direction from the point towards the light and the amount of the light reaching the point
+    pub fn illuminate(&self, world_point: &Point3<f64>) -> Option<(Vector3<f64>, f64)> {
+        match self.kind {
+            LightKind::Directional => Some((-self.direction.normalize(), self.intensity)),
+            LightKind::Point | LightKind::Spot => {
+                let to_light = self.position - world_point;
+                let distance = to_light.norm();
+                if distance == 0. {
+                    return None;
+                }
+                let to_light = to_light / distance;
+
+                let attenuation = 1. / (
+                    self.attenuation_constant +
+                    self.attenuation_linear * distance +
+                    self.attenuation_quadratic * distance * distance
+                );
+
+                let mut cone = 1.;
+                if self.kind == LightKind::Spot {
+                    let cos_theta = (-to_light).dot(&self.direction.normalize());
+                    let cos_inner = self.inner_cone.cos();
+                    let cos_outer = self.outer_cone.cos();
+                    cone = ((cos_theta - cos_outer) / (cos_inner - cos_outer).max(1e-6)).clamp(0., 1.);
+                }
+
+                if cone == 0. {
+                    None
+                } else {
+                    Some((to_light, self.intensity * attenuation * cone))
+                }
+            }
+        }
+    }
+}
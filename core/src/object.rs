@@ -1,5 +1,35 @@
+use std::rc::Rc;
+use std::cell::OnceCell;
+
 use crate::types::*;
-use crate::texture::Texture;
+use crate::texture::{Texture, Sampler, ColorSpace};
+use crate::shader::{Shader, PhongShader, PbrShader};
+use crate::bvh::{Bvh, Hit};
+use crate::bounds::Aabb;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ShadingModel {
+    Phong,
+    // metallic-roughness Cook-Torrance
+    Pbr
+}
+
+impl ShadingModel {
+    pub fn from_id(model_id: u32) -> Option<ShadingModel> {
+        match model_id {
+            1 => Some(ShadingModel::Phong),
+            2 => Some(ShadingModel::Pbr),
+            _ => None
+        }
+    }
+
+    pub fn shader(self) -> Rc<dyn Shader> {
+        match self {
+            ShadingModel::Phong => Rc::new(PhongShader),
+            ShadingModel::Pbr => Rc::new(PbrShader)
+        }
+    }
+}
 
 pub struct Object {
     pub vertices: Vec<Vertex>,
@@ -7,24 +37,48 @@ pub struct Object {
     pub texture_vertices: Vec<Vertex>,
     pub vertices_viewvable: Vec<bool>,
     pub faces: Vec<Face>,
+    // built on the first ray cast after the geometry changes
+    bvh: OnceCell<Bvh>,
+    // of the vertices in the object space
+    bounds: OnceCell<Aabb>,
     pub model_color: Color<f64>,
-    pub diffuse_texture: Texture<Pixel>,
+    pub diffuse_texture_data: Texture<Pixel>,
+    pub diffuse_texture_color: Texture<Color<f64>>,
     pub normal_texture_data: Texture<Pixel>,
     pub normal_texture_normals: Texture<Vector4<f64>>,
     pub specular_texture_data: Texture<Pixel>,
     pub specular_texture_coeff: Texture<Color<f64>>,
-    pub emission_texture: Texture<Pixel>,
+    pub emission_texture_data: Texture<Pixel>,
+    pub emission_texture_color: Texture<Color<f64>>,
+    pub metallic_texture_data: Texture<Pixel>,
+    pub metallic_texture_coeff: Texture<f64>,
+    pub roughness_texture_data: Texture<Pixel>,
+    pub roughness_texture_coeff: Texture<f64>,
+    pub ao_texture_data: Texture<Pixel>,
+    pub ao_texture_coeff: Texture<f64>,
     
     pub use_diffuse_texture: bool,
     pub use_normal_texture: bool,
     pub use_specular_texture: bool,
     pub use_emission_texture: bool,
+    pub use_metallic_texture: bool,
+    pub use_roughness_texture: bool,
+    pub use_ao_texture: bool,
+    // encoding of the uploaded data of each texture slot (texture_index - 1)
+    pub texture_color_spaces: [ColorSpace; 7],
+    // border colors as given, decoded into the samplers with the color space of the slot
+    pub texture_border_colors: [Color<u8>; 7],
+
+    // computes the color of the object's pixels, shared between the objects
+    pub shader: Rc<dyn Shader>,
 
     pub ambient: Color<f64>,
     pub diffuse_intensity: Color<f64>,
     pub specular: Color<f64>,
     pub specular_intensity: Color<f64>,
     pub shininess: f64,
+    pub metallic: f64,
+    pub roughness: f64,
     
     // world_position stuff
     pub rotation_matrix: Matrix4<f64>,
@@ -41,23 +95,43 @@ impl Object {
             texture_vertices: Vec::new(),
             faces: Vec::new(),
             model_color: Color{ r: 1., g: 1., b: 1. },
-            diffuse_texture: Texture::new(),
+            diffuse_texture_data: Texture::new(),
+            diffuse_texture_color: Texture::new(),
             normal_texture_data: Texture::new(),
             normal_texture_normals: Texture::new(),
             specular_texture_data: Texture::new(),
             specular_texture_coeff: Texture::new(),
-            emission_texture: Texture::new(),
+            emission_texture_data: Texture::new(),
+            emission_texture_color: Texture::new(),
+            metallic_texture_data: Texture::new(),
+            metallic_texture_coeff: Texture::new(),
+            roughness_texture_data: Texture::new(),
+            roughness_texture_coeff: Texture::new(),
+            ao_texture_data: Texture::new(),
+            ao_texture_coeff: Texture::new(),
 
             use_diffuse_texture: false,
             use_normal_texture: false,
             use_specular_texture: false,
             use_emission_texture: false,
+            use_metallic_texture: false,
+            use_roughness_texture: false,
+            use_ao_texture: false,
+            texture_color_spaces: [
+                ColorSpace::Srgb, ColorSpace::Linear, ColorSpace::Srgb, ColorSpace::Srgb,
+                ColorSpace::Linear, ColorSpace::Linear, ColorSpace::Linear
+            ],
+            texture_border_colors: [Color { r: 0, g: 0, b: 0 }; 7],
+
+            shader: ShadingModel::Phong.shader(),
             
             ambient: Color{ r: 0.1, g: 0.1, b: 0.1 },
             diffuse_intensity: Color{ r: 0.8, g: 0.8, b: 0.8 },
             specular: Color{ r: 1., g: 1., b: 1. },
             specular_intensity: Color{ r: 0.1, g: 0.1, b: 0.1 },
             shininess: 32.,
+            metallic: 0.,
+            roughness: 0.5,
 
             // emerald
             // ambient: Color{ r: 0.0215, g: 0.1745, b: 0.0215 },
@@ -79,13 +153,17 @@ impl Object {
 
             rotation_matrix: _one(),
             scale_matrix: _one(),
-            translation_matrix: _one()
+            translation_matrix: _one(),
+            bvh: OnceCell::new(),
+            bounds: OnceCell::new()
         }
     }
 
     pub fn add_vertex(&mut self, x: f64, y: f64, z: f64) {
         self.vertices.push(Vertex::new(x, y, z, 1.));
         self.vertices_viewvable.push(true);
+        self.bvh = OnceCell::new();
+        self.bounds = OnceCell::new();
     }
 
     pub fn add_vertex_normal(&mut self, x: f64, y: f64, z: f64) {
@@ -107,10 +185,38 @@ impl Object {
                 normal: a.cross(&b).normalize().to_homogeneous()
             }
         );
+        self.bvh = OnceCell::new();
         self.faces.len() - 1
     }
 
 
+    // nearest hit of the world space ray, the distance is in the units of the direction
+    pub fn raycast(&self, origin: &Point3<f64>, direction: &Vector3<f64>) -> Option<Hit> {
+        let to_object = self.get_to_world_matrix().try_inverse()?;
+        let object_origin = (to_object * origin.to_homogeneous()).xyz();
+        // not normalized, so the distance along the ray stays the same in both spaces
+        let object_direction = (to_object * direction.to_homogeneous()).xyz();
+
+        let bvh = self.bvh.get_or_init(|| Bvh::build(&self.vertices, &self.faces));
+        bvh.raycast(&self.vertices, &self.faces, &object_origin, &object_direction)
+    }
+
+    // bounding box in the world space, empty for the object without vertices
+    pub fn world_bounds(&self) -> Aabb {
+        let bounds = self.bounds.get_or_init(|| {
+            let mut bounds = Aabb::empty();
+            for vertex in self.vertices.iter() {
+                bounds.grow(&vertex.xyz());
+            }
+            bounds
+        });
+        bounds.transform(&self.get_to_world_matrix())
+    }
+
+    pub fn get_to_world_matrix(&self) -> Matrix4<f64> {
+        self.translation_matrix * self.scale_matrix * self.rotation_matrix
+    }
+
     pub fn set_rotation(&mut self, angle_x: f64, angle_y: f64, angle_z: f64) {
         let sinx = angle_x.sin();
         let cosx = angle_x.cos();
@@ -163,9 +269,7 @@ impl Object {
     }
 
     pub fn set_color(&mut self, r: u8, g: u8, b: u8) {
-        self.model_color.r = r as f64 / 255.;
-        self.model_color.g = g as f64 / 255.;
-        self.model_color.b = b as f64 / 255.;
+        self.model_color = ColorSpace::Srgb.decode_color(&Color { r, g, b });
     }
 
     pub fn set_ambient(&mut self, r: f64, g: f64, b: f64) {
@@ -189,7 +293,10 @@ impl Object {
 
     pub fn set_texture_size(&mut self, texture_index: usize, width: usize, height: usize) {
         match texture_index {
-            1 => self.diffuse_texture.set_size(width, height, Pixel{ color: white_color, a: 0}),
+            1 => {
+                self.diffuse_texture_data.set_size(width, height, Pixel{ color: white_color, a: 0});
+                self.diffuse_texture_color.set_size(width, height, Color{ r: 1., g: 1., b: 1.});
+            },
             2 => {
                 self.normal_texture_data.set_size(width, height, Pixel{ color: white_color, a: 0});
                 self.normal_texture_normals.set_size(width, height, Vector4::new(1., 1., 1., 1.));
@@ -198,39 +305,135 @@ impl Object {
                 self.specular_texture_data.set_size(width, height, Pixel{ color: white_color, a: 0});
                 self.specular_texture_coeff.set_size(width, height, Color{ r: 1., g: 1., b: 1.});
             },
-            4 => self.emission_texture.set_size(width, height, Pixel{color: black_color, a: 0}),
+            4 => {
+                self.emission_texture_data.set_size(width, height, Pixel{color: black_color, a: 0});
+                self.emission_texture_color.set_size(width, height, Color{ r: 0., g: 0., b: 0.});
+            },
+            5 => {
+                self.metallic_texture_data.set_size(width, height, Pixel{ color: black_color, a: 0});
+                self.metallic_texture_coeff.set_size(width, height, 0.);
+            },
+            6 => {
+                self.roughness_texture_data.set_size(width, height, Pixel{ color: white_color, a: 0});
+                self.roughness_texture_coeff.set_size(width, height, 1.);
+            },
+            7 => {
+                self.ao_texture_data.set_size(width, height, Pixel{ color: white_color, a: 0});
+                self.ao_texture_coeff.set_size(width, height, 1.);
+            },
             _ => {}
         }
     }
 
     pub fn get_texture_pixels(&mut self, texture_index: usize) -> *const Pixel {
         match texture_index {
-            1 => self.diffuse_texture.get_data_pointer(),
+            1 => self.diffuse_texture_data.get_data_pointer(),
             2 => self.normal_texture_data.get_data_pointer(),
             3 => self.specular_texture_data.get_data_pointer(),
-            4 => self.emission_texture.get_data_pointer(),
+            4 => self.emission_texture_data.get_data_pointer(),
+            5 => self.metallic_texture_data.get_data_pointer(),
+            6 => self.roughness_texture_data.get_data_pointer(),
+            7 => self.ao_texture_data.get_data_pointer(),
             _ => std::ptr::null()
         }
     }
 
+    pub fn get_texture_sampler(&mut self, texture_index: usize) -> Option<&mut Sampler> {
+        match texture_index {
+            1 => Some(&mut self.diffuse_texture_color.sampler),
+            2 => Some(&mut self.normal_texture_normals.sampler),
+            3 => Some(&mut self.specular_texture_coeff.sampler),
+            4 => Some(&mut self.emission_texture_color.sampler),
+            5 => Some(&mut self.metallic_texture_coeff.sampler),
+            6 => Some(&mut self.roughness_texture_coeff.sampler),
+            7 => Some(&mut self.ao_texture_coeff.sampler),
+            _ => None
+        }
+    }
+
+    pub fn is_texture_used(&self, texture_index: usize) -> bool {
+        match texture_index {
+            1 => self.use_diffuse_texture,
+            2 => self.use_normal_texture,
+            3 => self.use_specular_texture,
+            4 => self.use_emission_texture,
+            5 => self.use_metallic_texture,
+            6 => self.use_roughness_texture,
+            7 => self.use_ao_texture,
+            _ => false
+        }
+    }
+
+    pub fn decode_texture_border_color(&mut self, texture_index: usize) {
+        if !(1..=7).contains(&texture_index) { return }
+
+        let border_color = self.texture_color_spaces[texture_index - 1].decode_color(&self.texture_border_colors[texture_index - 1]);
+        if let Some(sampler) = self.get_texture_sampler(texture_index) {
+            sampler.border_color = border_color;
+        }
+    }
+
+    // decodes the uploaded data of the texture slot and builds its mips
+    pub fn prepare_texture(&mut self, texture_index: usize) {
+        if !(1..=7).contains(&texture_index) { return }
+
+        let color_space = self.texture_color_spaces[texture_index - 1];
+        match texture_index {
+            1 => {
+                Self::decode_color_texture(&self.diffuse_texture_data, &mut self.diffuse_texture_color, color_space);
+                self.diffuse_texture_color.generate_mips();
+            },
+            2 => {
+                self.normalize_normal_texture();
+                self.normal_texture_normals.generate_mips();
+            },
+            3 => {
+                Self::decode_color_texture(&self.specular_texture_data, &mut self.specular_texture_coeff, color_space);
+                self.specular_texture_coeff.generate_mips();
+            },
+            4 => {
+                Self::decode_color_texture(&self.emission_texture_data, &mut self.emission_texture_color, color_space);
+                self.emission_texture_color.generate_mips();
+            },
+            5 => {
+                Self::normalize_grayscale_texture(&self.metallic_texture_data, &mut self.metallic_texture_coeff, color_space);
+                self.metallic_texture_coeff.generate_mips();
+            },
+            6 => {
+                Self::normalize_grayscale_texture(&self.roughness_texture_data, &mut self.roughness_texture_coeff, color_space);
+                self.roughness_texture_coeff.generate_mips();
+            },
+            7 => {
+                Self::normalize_grayscale_texture(&self.ao_texture_data, &mut self.ao_texture_coeff, color_space);
+                self.ao_texture_coeff.generate_mips();
+            },
+            _ => {}
+        }
+    }
+
     pub fn normalize_normal_texture(&mut self) {
-        for (normal_index, normal_color) in self.normal_texture_data.data.iter_mut().enumerate() {
+        let color_space = self.texture_color_spaces[1];
+        for (normal_index, normal_color) in self.normal_texture_data.data.iter().enumerate() {
+            let normal = color_space.decode_color(&normal_color.color);
             self.normal_texture_normals.data[normal_index] = Vector4::new(
-                normal_color.color.r as f64 / 255. * 2. - 1.,
-                normal_color.color.g as f64 / 255. * 2. - 1.,
-                normal_color.color.b as f64 / 255. * 2. - 1.,
+                normal.r * 2. - 1.,
+                normal.g * 2. - 1.,
+                normal.b * 2. - 1.,
                 0.
             )
         }
     }
 
-    pub fn normalize_specular_texture(&mut self) {
-        for (specular_index, specular_color) in self.specular_texture_data.data.iter_mut().enumerate() {
-            self.specular_texture_coeff.data[specular_index] = Color {
-                r: specular_color.color.r as f64 / 255.,
-                g: specular_color.color.g as f64 / 255.,
-                b: specular_color.color.b as f64 / 255.
-            }
+    pub fn decode_color_texture(data: &Texture<Pixel>, color: &mut Texture<Color<f64>>, color_space: ColorSpace) {
+        for (index, pixel) in data.data.iter().enumerate() {
+            color.data[index] = color_space.decode_color(&pixel.color);
         }
     }
-}
\ No newline at end of file
+
+    // grayscale maps, the red channel is used
+    pub fn normalize_grayscale_texture(data: &Texture<Pixel>, coeff: &mut Texture<f64>, color_space: ColorSpace) {
+        for (index, pixel) in data.data.iter().enumerate() {
+            coeff.data[index] = color_space.decode(pixel.color.r);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use crate::types::*;
+use crate::camera::Camera;
+use crate::console::log;
+
+// motion below it is treated as finished
+const REST_THRESHOLD: f64 = 1e-5;
+
+// turntable around the target point: yaw around the world up, pitch limited to not flip over the poles
+pub struct OrbitController {
+    pub enabled: bool,
+    pub target: Point3<f64>,
+    pub distance: f64,
+    yaw: f64,
+    pitch: f64,
+    // radians per pixel of the mouse movement
+    pub rotate_speed: f64,
+    // distance change per wheel step, 0.1 - 10%
+    pub zoom_speed: f64,
+    // time constant of the inertia in seconds, the remaining motion falls e times over it; 0 - no inertia
+    pub inertia: f64,
+    pub min_pitch: f64,
+    pub max_pitch: f64,
+    pub min_distance: f64,
+    pub max_distance: f64,
+    // motion still to be applied: yaw, pitch; pan in the world space; log of the distance scale
+    rotation_left: Vector2<f64>,
+    pan_left: Vector3<f64>,
+    zoom_left: f64,
+    // the point on the target plane the zoom moves towards
+    zoom_point: Point3<f64>
+}
+
+impl OrbitController {
+    pub fn new() -> OrbitController {
+        OrbitController {
+            enabled: false,
+            target: Point3::origin(),
+            distance: 3.,
+            yaw: 0.,
+            pitch: 0.,
+            rotate_speed: 0.01,
+            zoom_speed: 0.1,
+            inertia: 0.06,
+            min_pitch: (-89_f64).to_radians(),
+            max_pitch: 89_f64.to_radians(),
+            min_distance: 1e-2,
+            max_distance: 1e4,
+            rotation_left: Vector2::zeros(),
+            pan_left: Vector3::zeros(),
+            zoom_left: 0.,
+            zoom_point: Point3::origin()
+        }
+    }
+
+    pub fn set_param(&mut self, param_id: u32, param_value: f64) {
+        match param_id {
+            1 => self.rotate_speed = param_value,
+            2 => self.zoom_speed = param_value.max(0.),
+            3 => self.inertia = param_value.max(0.),
+            4 => self.min_pitch = param_value.clamp(-89.9, 89.9).to_radians(),
+            5 => self.max_pitch = param_value.clamp(-89.9, 89.9).to_radians(),
+            6 => self.min_distance = param_value.max(1e-6),
+            7 => self.max_distance = param_value.max(1e-6),
+            _ => log("Not recognized orbit param")
+        }
+    }
+
+    // takes over the camera around its pivot, so the view does not jump
+    pub fn attach(&mut self, camera: &Camera) {
+        self.enabled = true;
+        self.target = camera.pivot();
+        self.distance = camera.pivot_distance;
+        self.yaw = f64::atan2(camera.front[0], camera.front[2]);
+        self.pitch = camera.front[1].clamp(-1., 1.).asin();
+        self.stop();
+    }
+
+    pub fn stop(&mut self) {
+        self.rotation_left = Vector2::zeros();
+        self.pan_left = Vector3::zeros();
+        self.zoom_left = 0.;
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.rotation_left.norm() > REST_THRESHOLD ||
+        self.pan_left.norm() > REST_THRESHOLD * self.distance ||
+        self.zoom_left.abs() > REST_THRESHOLD
+    }
+
+    fn front(&self) -> Vector3<f64> {
+        Vector3::new(
+            self.yaw.sin() * self.pitch.cos(),
+            self.pitch.sin(),
+            self.yaw.cos() * self.pitch.cos()
+        )
+    }
+
+    // the motion is spread over the next updates, in total it is the same as the input unless the pitch reaches its limits
+    pub fn rotate(&mut self, dx: f64, dy: f64) {
+        self.rotation_left += Vector2::new(-dx, -dy) * self.rotate_speed;
+    }
+
+    // moves the target so the point under the cursor follows it
+    pub fn pan(&mut self, dx: f64, dy: f64, camera: &Camera, image_height: f64) {
+        let world_per_pixel = 2. * self.distance * f64::tan(camera.fov / 2.) / image_height;
+        let world_per_pixel = if camera.projection.is_orthographic() {
+            world_per_pixel / camera.zoom
+        } else {
+            world_per_pixel
+        };
+        let front = self.front();
+        let right = front.cross(&camera.world_up).normalize();
+        let up = right.cross(&front);
+        self.pan_left += (right * -dx + up * dy) * world_per_pixel;
+    }
+
+    // steps > 0 come closer; ndc_x and ndc_y are the cursor in the normalized device coordinates
+    pub fn zoom(&mut self, steps: f64, ndc_x: f64, ndc_y: f64, camera: &Camera) {
+        // the cursor ray hits the plane through the target facing the camera
+        self.zoom_point = if camera.projection.is_orthographic() {
+            let half_height = camera.orthographic_height() / 2.;
+            let right = camera.front.cross(&camera.world_up).normalize();
+            let up = right.cross(&camera.front);
+            camera.pivot() + right * (ndc_x * half_height * camera.aspect) + up * (ndc_y * half_height)
+        } else {
+            let direction = camera.get_view_direction(ndc_x, ndc_y);
+            camera.position + direction * (camera.pivot_distance / direction.dot(&camera.front))
+        };
+        self.zoom_left += (1. - self.zoom_speed).max(1e-3).ln() * steps;
+    }
+
+    // applies the part of the remaining motion due in dt seconds and places the camera;
+    // the remaining motion decays exponentially, so the rotation and the zoom add up the same however dt is split,
+    // only the pitch and distance limits, a pan during the zoom and the stop at rest make the path depend on the steps
+    pub fn update(&mut self, dt: f64, camera: &mut Camera) {
+        let part = if self.inertia > 0. { 1. - (-dt / self.inertia).exp() } else { 1. };
+        let (rotation, pan, zoom) = (self.rotation_left * part, self.pan_left * part, self.zoom_left * part);
+
+        self.yaw += rotation[0];
+        self.pitch = (self.pitch + rotation[1]).clamp(self.min_pitch, self.max_pitch);
+        self.target += pan;
+
+        let distance = (self.distance * zoom.exp()).clamp(self.min_distance, self.max_distance);
+        // the zoom point keeps its place on the screen
+        let zoom_point = self.zoom_point + pan;
+        self.target = zoom_point + (self.target - zoom_point) * (distance / self.distance);
+        self.zoom_point = zoom_point;
+        self.distance = distance;
+
+        self.rotation_left -= rotation;
+        self.pan_left -= pan;
+        self.zoom_left -= zoom;
+        if !self.is_moving() {
+            self.stop();
+        }
+
+        let front = self.front();
+        camera.set_pose(&(self.target - front * self.distance), &front, self.distance);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> Camera {
+        Camera::new(
+            60_f64.to_radians(), 4. / 3., 0.1, 100.,
+            &Point3::new(1.5, 1.5, 1.5), &Vector3::new(-1., -1., -1.).normalize(), &Vector3::new(0., 1., 0.)
+        )
+    }
+
+    fn settle(orbit: &mut OrbitController, camera: &mut Camera) {
+        for _ in 0..1000 {
+            orbit.update(0.016, camera);
+        }
+        assert!(!orbit.is_moving());
+    }
+
+    // normalized device coordinates of the world point
+    fn to_ndc(camera: &Camera, point: &Point3<f64>) -> Vector2<f64> {
+        let v = camera.projection_matrix * camera.look_at_matrix * point.to_homogeneous();
+        Vector2::new(v[0] / v[3], v[1] / v[3])
+    }
+
+    #[test]
+    fn attach_keeps_view() {
+        let mut camera = camera();
+        let (position, front) = (camera.position, camera.front);
+        let mut orbit = OrbitController::new();
+        orbit.attach(&camera);
+        orbit.update(0.016, &mut camera);
+        assert!((camera.position - position).norm() < 1e-9);
+        assert!((camera.front - front).norm() < 1e-9);
+    }
+
+    #[test]
+    fn rotation_adds_up_to_input() {
+        let mut camera = camera();
+        let mut orbit = OrbitController::new();
+        orbit.attach(&camera);
+        let yaw = orbit.yaw;
+        orbit.rotate(30., 0.);
+        settle(&mut orbit, &mut camera);
+        assert!((orbit.yaw - (yaw - 30. * orbit.rotate_speed)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn pitch_stays_within_limits() {
+        let mut camera = camera();
+        let mut orbit = OrbitController::new();
+        orbit.attach(&camera);
+        orbit.set_param(4, -30.);
+        orbit.set_param(5, 60.);
+
+        orbit.rotate(0., -1e4);
+        settle(&mut orbit, &mut camera);
+        assert!((camera.front[1].asin() - 60_f64.to_radians()).abs() < 1e-9);
+
+        orbit.rotate(0., 1e4);
+        settle(&mut orbit, &mut camera);
+        assert!((camera.front[1].asin() + 30_f64.to_radians()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn zoom_keeps_point_under_cursor() {
+        let mut camera = camera();
+        let mut orbit = OrbitController::new();
+        orbit.attach(&camera);
+        let cursor = Vector2::new(0.5, -0.25);
+        let direction = camera.get_view_direction(cursor[0], cursor[1]);
+        let point = camera.position + direction * (camera.pivot_distance / direction.dot(&camera.front));
+        assert!((to_ndc(&camera, &point) - cursor).norm() < 1e-9);
+
+        orbit.zoom(3., cursor[0], cursor[1], &camera);
+        settle(&mut orbit, &mut camera);
+        assert!(orbit.distance < 3. * 0.8);
+        assert!((to_ndc(&camera, &point) - cursor).norm() < 1e-9);
+    }
+
+    // the camera in the middle of the rotation and the zoom, after 0.1 s updated in the equal steps
+    fn rotate_and_zoom(steps: usize) -> Camera {
+        let mut camera = camera();
+        let mut orbit = OrbitController::new();
+        orbit.attach(&camera);
+        orbit.rotate(30., 10.);
+        orbit.zoom(2., 0.5, -0.25, &camera);
+        for _ in 0..steps {
+            orbit.update(0.1 / steps as f64, &mut camera);
+        }
+        camera
+    }
+
+    #[test]
+    fn motion_does_not_depend_on_steps() {
+        let (slow, fast) = (rotate_and_zoom(4), rotate_and_zoom(144));
+        assert!((slow.position - fast.position).norm() < 1e-9);
+        assert!((slow.front - fast.front).norm() < 1e-9);
+        assert!((slow.pivot_distance - fast.pivot_distance).abs() < 1e-9);
+    }
+}
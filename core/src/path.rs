@@ -0,0 +1,386 @@
+use crate::types::*;
+use crate::camera::{self, Camera};
+use crate::console::log;
+
+// samples per segment of the arc length table
+const SEGMENT_SAMPLES: usize = 32;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum PathCurve {
+    // goes through every point
+    CatmullRom,
+    // cubic segments sharing the end points: point, two handles, point, two handles, point...
+    Bezier
+}
+
+impl PathCurve {
+    pub fn from_id(curve_id: u32) -> Option<PathCurve> {
+        match curve_id {
+            0 => Some(PathCurve::CatmullRom),
+            1 => Some(PathCurve::Bezier),
+            _ => None
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum PathLook {
+    // along the path
+    Forward,
+    Target,
+    // the directions given with the points, interpolated between them
+    PointDirections
+}
+
+impl PathLook {
+    pub fn from_id(look_id: u32) -> Option<PathLook> {
+        match look_id {
+            0 => Some(PathLook::Forward),
+            1 => Some(PathLook::Target),
+            2 => Some(PathLook::PointDirections),
+            _ => None
+        }
+    }
+}
+
+struct PathPoint {
+    position: Point3<f64>,
+    direction: Option<Vector3<f64>>
+}
+
+// the camera moves along the curve with the constant speed
+pub struct CameraPath {
+    pub curve: PathCurve,
+    pub look: PathLook,
+    pub target: Point3<f64>,
+    // units per second
+    pub speed: f64,
+    // starts again after the end
+    pub looping: bool,
+    // the last point is joined with the first one
+    pub closed: bool,
+    pub playing: bool,
+    points: Vec<PathPoint>,
+    // cumulative length at every sample of the curve parameter, rebuilt when the curve changes
+    lengths: Vec<f64>,
+    // traveled distance
+    distance: f64
+}
+
+impl CameraPath {
+    pub fn new() -> CameraPath {
+        CameraPath {
+            curve: PathCurve::CatmullRom,
+            look: PathLook::Forward,
+            target: Point3::origin(),
+            speed: 1.,
+            looping: false,
+            closed: false,
+            playing: false,
+            points: Vec::new(),
+            lengths: Vec::new(),
+            distance: 0.
+        }
+    }
+
+    pub fn set_param(&mut self, param_id: u32, param_value: f64) {
+        match param_id {
+            1 => self.speed = param_value,
+            2 => self.looping = param_value != 0.,
+            3 => {
+                self.closed = param_value != 0.;
+                self.invalidate();
+            },
+            _ => log("Not recognized camera path param")
+        }
+    }
+
+    pub fn set_curve(&mut self, curve: PathCurve) {
+        self.curve = curve;
+        self.invalidate();
+    }
+
+    pub fn clear(&mut self) {
+        self.points.clear();
+        self.playing = false;
+        self.invalidate();
+    }
+
+    pub fn add_point(&mut self, position: Point3<f64>) {
+        self.points.push(PathPoint { position, direction: None });
+        self.invalidate();
+    }
+
+    pub fn set_point_direction(&mut self, point_index: usize, direction: Vector3<f64>) {
+        if let Some(point) = self.points.get_mut(point_index) {
+            point.direction = direction.try_normalize(1e-12);
+        }
+    }
+
+    fn invalidate(&mut self) {
+        self.lengths.clear();
+        self.distance = 0.;
+    }
+
+    fn segment_count(&self) -> usize {
+        let count = self.points.len();
+        match self.curve {
+            PathCurve::CatmullRom if count >= 2 => if self.closed { count } else { count - 1 },
+            PathCurve::Bezier if count >= 4 => (count - 1) / 3,
+            _ => 0
+        }
+    }
+
+    fn point(&self, index: isize) -> Point3<f64> {
+        let count = self.points.len() as isize;
+        let index = if self.closed { index.rem_euclid(count) } else { index.clamp(0, count - 1) };
+        self.points[index as usize].position
+    }
+
+    // the point at the curve parameter, segment_count() has to be above 0
+    fn position(&self, parameter: f64) -> Point3<f64> {
+        let segment_count = self.segment_count();
+        let segment = (parameter.floor().max(0.) as usize).min(segment_count - 1);
+        let t = parameter - segment as f64;
+        let (p0, p1, p2, p3) = match self.curve {
+            PathCurve::CatmullRom => {
+                let i = segment as isize;
+                (self.point(i - 1), self.point(i), self.point(i + 1), self.point(i + 2))
+            },
+            PathCurve::Bezier => {
+                let i = segment as isize * 3;
+                (self.point(i), self.point(i + 1), self.point(i + 2), self.point(i + 3))
+            }
+        };
+        let (t2, t3) = (t * t, t * t * t);
+        let coords = match self.curve {
+            PathCurve::CatmullRom => (
+                p1.coords * 2. +
+                (p2 - p0) * t +
+                (p0.coords * 2. - p1.coords * 5. + p2.coords * 4. - p3.coords) * t2 +
+                (p1.coords * 3. - p0.coords - p2.coords * 3. + p3.coords) * t3
+            ) * 0.5,
+            PathCurve::Bezier => {
+                let s = 1. - t;
+                p0.coords * (s * s * s) + p1.coords * (3. * s * s * t) + p2.coords * (3. * s * t2) + p3.coords * t3
+            }
+        };
+        Point3::from(coords)
+    }
+
+    // direction given at the end of the segments: every point for catmull-rom, every third for bezier
+    fn knot_direction(&self, knot: usize) -> Option<Vector3<f64>> {
+        let index = match self.curve {
+            PathCurve::CatmullRom => knot % self.points.len(),
+            PathCurve::Bezier => knot * 3
+        };
+        self.points.get(index)?.direction
+    }
+
+    fn build_lengths(&mut self) {
+        let sample_count = self.segment_count() * SEGMENT_SAMPLES;
+        self.lengths.clear();
+        if sample_count == 0 {
+            return;
+        }
+        self.lengths.push(0.);
+        let mut previous = self.position(0.);
+        for i in 1..=sample_count {
+            let point = self.position(i as f64 / SEGMENT_SAMPLES as f64);
+            let length = self.lengths[i - 1] + (point - previous).norm();
+            self.lengths.push(length);
+            previous = point;
+        }
+    }
+
+    pub fn length(&mut self) -> f64 {
+        if self.lengths.is_empty() {
+            self.build_lengths();
+        }
+        self.lengths.last().copied().unwrap_or(0.)
+    }
+
+    // curve parameter of the point at the distance along the curve
+    fn parameter_at(&self, distance: f64) -> f64 {
+        let i = self.lengths.partition_point(|length| *length < distance).clamp(1, self.lengths.len() - 1);
+        let (before, after) = (self.lengths[i - 1], self.lengths[i]);
+        let part = if after > before { (distance - before) / (after - before) } else { 0. };
+        (i as f64 - 1. + part.clamp(0., 1.)) / SEGMENT_SAMPLES as f64
+    }
+
+    // part of the path passed, 0..1
+    pub fn progress(&mut self) -> f64 {
+        let length = self.length();
+        if length > 0. { self.distance / length } else { 0. }
+    }
+
+    pub fn seek(&mut self, progress: f64) {
+        self.distance = progress.clamp(0., 1.) * self.length();
+    }
+
+    pub fn play(&mut self) {
+        let length = self.length();
+        if length <= 0. {
+            log("Camera path needs more points");
+            return;
+        }
+        // playing the finished path starts it again, from the end when it is played backwards
+        if !self.looping {
+            if self.speed >= 0. && self.distance >= length {
+                self.distance = 0.;
+            } else if self.speed < 0. && self.distance <= 0. {
+                self.distance = length;
+            }
+        }
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+        self.distance = 0.;
+    }
+
+    // moves along the path by dt seconds
+    pub fn update(&mut self, dt: f64) {
+        let length = self.length();
+        if !self.playing || length <= 0. {
+            return;
+        }
+        self.distance += self.speed * dt;
+        if self.looping {
+            self.distance = self.distance.rem_euclid(length);
+        } else if (self.speed > 0. && self.distance >= length) || (self.speed < 0. && self.distance <= 0.) {
+            // only passing the end in the direction of travel stops it
+            self.distance = self.distance.clamp(0., length);
+            self.playing = false;
+        }
+    }
+
+    // puts the camera on the path at the traveled distance
+    pub fn place(&mut self, camera: &mut Camera) {
+        let length = self.length();
+        if length <= 0. {
+            return;
+        }
+        let parameter = self.parameter_at(self.distance);
+        let position = self.position(parameter);
+
+        // the tangent over a short piece of the path around the point
+        let step = (length * 1e-3).min(1e-2);
+        let (behind, ahead) = if self.closed {
+            ((self.distance - step).rem_euclid(length), (self.distance + step).rem_euclid(length))
+        } else {
+            ((self.distance - step).max(0.), (self.distance + step).min(length))
+        };
+        let forward = (self.position(self.parameter_at(ahead)) - self.position(self.parameter_at(behind)))
+            .try_normalize(1e-12)
+            .unwrap_or(camera.front);
+
+        let mut pivot_distance = camera.pivot_distance;
+        let front = match self.look {
+            PathLook::Forward => forward,
+            PathLook::Target => {
+                pivot_distance = (self.target - position).norm().max(1e-6);
+                (self.target - position).try_normalize(1e-12).unwrap_or(forward)
+            },
+            PathLook::PointDirections => {
+                let knot = parameter.floor() as usize;
+                let from = self.knot_direction(knot).unwrap_or(forward);
+                let to = self.knot_direction(knot + 1).unwrap_or(forward);
+                camera::slerp_direction(&from, &to, &camera.world_up, parameter - knot as f64)
+            }
+        };
+        camera.set_pose(&position, &front, pivot_distance);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // straight line of the length 3 along x
+    fn line() -> CameraPath {
+        let mut path = CameraPath::new();
+        path.add_point(Point3::new(0., 0., 0.));
+        path.add_point(Point3::new(1., 0., 0.));
+        path.add_point(Point3::new(3., 0., 0.));
+        path
+    }
+
+    fn camera() -> Camera {
+        Camera::new(1., 1., 0.1, 10., &Point3::new(0., 0., 5.), &Vector3::new(0., 0., -1.), &Vector3::new(0., 1., 0.))
+    }
+
+    #[test]
+    fn length_of_line() {
+        let mut path = line();
+        assert!((path.length() - 3.).abs() < 1e-9);
+        path.clear();
+        assert_eq!(path.length(), 0.);
+    }
+
+    #[test]
+    fn seek_places_camera_at_distance() {
+        let mut path = line();
+        let mut camera = camera();
+        path.seek(0.5);
+        assert!((path.progress() - 0.5).abs() < 1e-12);
+        path.place(&mut camera);
+        assert!((camera.position - Point3::new(1.5, 0., 0.)).norm() < 1e-3);
+        assert!((camera.front - Vector3::new(1., 0., 0.)).norm() < 1e-6);
+
+        path.seek(2.);
+        assert_eq!(path.progress(), 1.);
+        path.seek(-1.);
+        assert_eq!(path.progress(), 0.);
+    }
+
+    #[test]
+    fn plays_to_end_and_stops() {
+        let mut path = line();
+        path.play();
+        path.update(0.);
+        assert!(path.playing);
+        path.update(1.);
+        assert!(path.playing);
+        assert!((path.progress() - 1. / 3.).abs() < 1e-12);
+        path.update(5.);
+        assert!(!path.playing);
+        assert_eq!(path.progress(), 1.);
+
+        // the finished path starts again
+        path.play();
+        assert!(path.playing);
+        assert_eq!(path.progress(), 0.);
+    }
+
+    #[test]
+    fn plays_backwards_from_end() {
+        let mut path = line();
+        path.speed = -1.;
+        path.play();
+        assert_eq!(path.progress(), 1.);
+        path.update(0.);
+        assert!(path.playing);
+        path.update(2.);
+        assert!(path.playing);
+        assert!((path.progress() - 1. / 3.).abs() < 1e-12);
+        path.update(2.);
+        assert!(!path.playing);
+        assert_eq!(path.progress(), 0.);
+    }
+
+    #[test]
+    fn looping_wraps_around() {
+        let mut path = line();
+        path.looping = true;
+        path.play();
+        path.update(4.);
+        assert!(path.playing);
+        assert!((path.progress() - 1. / 3.).abs() < 1e-12);
+        path.speed = -1.;
+        path.update(2.);
+        assert!(path.playing);
+        assert!((path.progress() - 2. / 3.).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,36 @@
+use wasm_bindgen::prelude::wasm_bindgen;
+
+// what is under the pixel of the last computed frame, the positions and the normal are in the world space
+#[wasm_bindgen]
+#[derive(Copy, Clone)]
+pub struct PickResult {
+    pub object_handle: usize,
+    pub face_index: usize,
+    // weights of the three face vertices
+    pub barycentric_x: f64,
+    pub barycentric_y: f64,
+    pub barycentric_z: f64,
+    pub u: f64,
+    pub v: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub normal_x: f64,
+    pub normal_y: f64,
+    pub normal_z: f64,
+    // linear depth, the same one the fog uses
+    pub depth: f64
+}
+
+// the nearest face hit by a ray
+#[wasm_bindgen]
+#[derive(Copy, Clone)]
+pub struct RayHit {
+    pub object_handle: usize,
+    pub face_index: usize,
+    // world space distance along the normalized ray direction
+    pub distance: f64,
+    pub barycentric_x: f64,
+    pub barycentric_y: f64,
+    pub barycentric_z: f64
+}
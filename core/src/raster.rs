@@ -83,9 +83,25 @@ pub fn draw_face_on_buffer(width: i32, height: i32,
     is_partial: bool
 ) {
 
-    let mut v1 = &vertices[object_index][face.vertices_indexes[0] as usize];
-    let mut v2 = &vertices[object_index][face.vertices_indexes[1] as usize];
-    let mut v3 = &vertices[object_index][face.vertices_indexes[2] as usize];
+    let v1 = &vertices[object_index][face.vertices_indexes[0] as usize];
+    let v2 = &vertices[object_index][face.vertices_indexes[1] as usize];
+    let v3 = &vertices[object_index][face.vertices_indexes[2] as usize];
+
+    rasterize_face(width, height, v1, v2, v3, is_partial, |index, z| {
+        if z < z_buf[index] {
+            z_buf[index] = z;
+            face_index_buffer[index] = face_index as i32;
+            object_index_buffer[index] = object_index as i32;
+        }
+    });
+}
+
+// walks through the pixels of the face calling plot with the pixel index and interpolated z
+pub fn rasterize_face<'a, F>(width: i32, height: i32,
+    mut v1: &'a Vertex, mut v2: &'a Vertex, mut v3: &'a Vertex,
+    is_partial: bool,
+    mut plot: F
+) where F: FnMut(usize, f64) {
 
     // sort on y
     if v2[1] < v1[1] {
@@ -145,12 +161,7 @@ pub fn draw_face_on_buffer(width: i32, height: i32,
             let mut z_j = wz1;
             for j in wx1.round() as i32 ..= wx2.round() as i32 {
                 if !is_partial || !(j < 0 || j >= width) {
-                    let index = get_index(i as u32, j as u32, width as u32);
-                    if z_j < z_buf[index] {
-                        z_buf[index] = z_j;
-                        face_index_buffer[index] = face_index as i32;
-                        object_index_buffer[index] = object_index as i32;
-                    }
+                    plot(get_index(i as u32, j as u32, width as u32), z_j);
                 }
                 z_j += dz_k;
             }
@@ -188,12 +199,7 @@ pub fn draw_face_on_buffer(width: i32, height: i32,
             let mut z_j = wz1;
             for j in wx1.round() as i32 ..= wx2.round() as i32 {
                 if !is_partial || !(j < 0 || j >= width) {
-                    let index = get_index(i as u32, j as u32, width as u32);
-                    if z_j < z_buf[index] {
-                        z_buf[index] = z_j;
-                        face_index_buffer[index] = face_index as i32;
-                        object_index_buffer[index] = object_index as i32;
-                    }
+                    plot(get_index(i as u32, j as u32, width as u32), z_j);
                 }
                 z_j += dz_k;
             }
@@ -229,6 +235,17 @@ pub fn calc_barycentric(
     Point3::new(u, v, w)
 }
 
+// barycentric coordinates of the screen point (x, y), the point is not required to be inside the face
+pub fn calc_screen_barycentric(
+    x: f64, y: f64,
+    v1: &Vertex, v2: &Vertex, v3: &Vertex
+) -> Point3<f64> {
+    let denom = (v2[1] - v3[1]) * (v1[0] - v3[0]) + (v3[0] - v2[0]) * (v1[1] - v3[1]);
+    let u = ((v2[1] - v3[1]) * (x - v3[0]) + (v3[0] - v2[0]) * (y - v3[1])) / denom;
+    let v = ((v3[1] - v1[1]) * (x - v3[0]) + (v1[0] - v3[0]) * (y - v3[1])) / denom;
+    Point3::new(u, v, 1. - u - v)
+}
+
 pub fn lerp(
     brc: &Point3<f64>,
     v1: &Vector4<f64>, v2: &Vector4<f64>, v3: &Vector4<f64>,
@@ -0,0 +1,167 @@
+use std::collections::HashSet;
+
+use crate::types::*;
+use crate::raster;
+use crate::object::Object;
+use crate::console::log;
+
+// mask values of the selected pixels
+const HIDDEN: u8 = 1;
+const VISIBLE: u8 = 2;
+
+pub struct Selection {
+    objects: HashSet<usize>,
+    // (object handle, face index)
+    faces: HashSet<(usize, usize)>,
+    // outline thickness in pixels, 0 - no outline
+    pub outline_width: i32,
+    pub outline_color: Color<u8>,
+    // the visible selected pixels are blended with the tint, 0 - no overlay
+    pub tint_color: Color<u8>,
+    pub tint_strength: f64,
+    // the outline of the occluded parts is drawn dashed
+    pub xray: bool,
+    pub dash_length: usize,
+    mask: Vec<u8>
+}
+
+impl Selection {
+    pub fn new() -> Selection {
+        Selection {
+            objects: HashSet::new(),
+            faces: HashSet::new(),
+            outline_width: 2,
+            outline_color: Color { r: 255, g: 160, b: 0 },
+            tint_color: Color { r: 255, g: 160, b: 0 },
+            tint_strength: 0.,
+            xray: true,
+            dash_length: 4,
+            mask: Vec::new()
+        }
+    }
+
+    pub fn set_param(&mut self, param_id: u32, param_value: f64) {
+        match param_id {
+            1 => self.outline_width = param_value.max(0.) as i32,
+            2 => self.tint_strength = param_value.clamp(0., 1.),
+            3 => self.xray = param_value != 0.,
+            4 => self.dash_length = param_value.max(1.) as usize,
+            _ => log("Not recognized selection param")
+        }
+    }
+
+    pub fn select_object(&mut self, object_handle: usize, selected: bool) {
+        if selected {
+            self.objects.insert(object_handle);
+        } else {
+            self.objects.remove(&object_handle);
+        }
+    }
+
+    pub fn select_face(&mut self, object_handle: usize, face_index: usize, selected: bool) {
+        if selected {
+            self.faces.insert((object_handle, face_index));
+        } else {
+            self.faces.remove(&(object_handle, face_index));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.objects.clear();
+        self.faces.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty() && self.faces.is_empty()
+    }
+
+    fn is_selected(&self, object_handle: usize, face_index: usize) -> bool {
+        self.objects.contains(&object_handle) || self.faces.contains(&(object_handle, face_index))
+    }
+
+    // marks the visible selected pixels from the index buffers, and for the x-ray the whole silhouette
+    // rasterized without the depth test
+    pub fn build_mask(
+        &mut self, width: usize, height: usize, objects: &[Object], view_vertices: &[Vec<Vertex>],
+        object_index_buffer: &[i32], face_index_buffer: &[i32]
+    ) {
+        self.mask.clear();
+        self.mask.resize(width * height, 0);
+
+        if self.xray {
+            for (object_index, obj) in objects.iter().enumerate() {
+                for (face_index, face) in obj.faces.iter().enumerate() {
+                    if !self.is_selected(object_index, face_index) {
+                        continue;
+                    }
+                    let (i0, i1, i2) = (face.vertices_indexes[0], face.vertices_indexes[1], face.vertices_indexes[2]);
+                    if !obj.vertices_viewvable[i0] && !obj.vertices_viewvable[i1] && !obj.vertices_viewvable[i2] {
+                        continue;
+                    }
+                    let is_partial = !(obj.vertices_viewvable[i0] && obj.vertices_viewvable[i1] && obj.vertices_viewvable[i2]);
+                    let vertices = &view_vertices[object_index];
+                    let mask = &mut self.mask;
+                    raster::rasterize_face(
+                        width as i32, height as i32, &vertices[i0], &vertices[i1], &vertices[i2], is_partial,
+                        |index, _| mask[index] = HIDDEN
+                    );
+                }
+            }
+        }
+
+        for (index, (object_index, face_index)) in object_index_buffer.iter().zip(face_index_buffer.iter()).enumerate() {
+            if *object_index >= 0 && self.is_selected(*object_index as usize, *face_index as usize) {
+                self.mask[index] = VISIBLE;
+            }
+        }
+    }
+
+    // draws over the final pixels, so neither the tone mapping nor the post effects touch the outline
+    pub fn apply(&self, pixels: &mut [Pixel], width: usize, height: usize) {
+        if self.tint_strength > 0. {
+            let tint = self.tint_color.to_f64();
+            for (pixel, mask) in pixels.iter_mut().zip(self.mask.iter()) {
+                if *mask == VISIBLE {
+                    pixel.color = (pixel.color.to_f64() * (1. - self.tint_strength) + tint * self.tint_strength).to_u8();
+                }
+            }
+        }
+
+        let radius = self.outline_width;
+        if radius == 0 {
+            return;
+        }
+        for y in 0..height {
+            for x in 0..width {
+                if self.mask[y * width + x] != 0 {
+                    continue;
+                }
+
+                // the strongest mask value around the pixel outside of the silhouette
+                let mut nearby = 0;
+                for offset_y in -radius..=radius {
+                    for offset_x in -radius..=radius {
+                        if offset_x * offset_x + offset_y * offset_y > radius * radius {
+                            continue;
+                        }
+                        let (sx, sy) = (x as i32 + offset_x, y as i32 + offset_y);
+                        if sx < 0 || sy < 0 || sx >= width as i32 || sy >= height as i32 {
+                            continue;
+                        }
+                        nearby = nearby.max(self.mask[sy as usize * width + sx as usize]);
+                    }
+                }
+
+                let is_outline = match nearby {
+                    VISIBLE => true,
+                    // every other diagonal segment
+                    HIDDEN => ((x + y) / self.dash_length) & 1 == 0,
+                    _ => false
+                };
+                if is_outline {
+                    pixels[y * width + x].color = self.outline_color;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::f64::consts::PI;
+
+use crate::brdf;
+use crate::light::Light;
+use crate::shadow::ShadowMap;
+use crate::environment::Environment;
+
+// the types needed to implement a shader outside of the crate
+pub use crate::types::{Color, Vector2, Vector3, Point3};
+pub use crate::object::Object;
+
+// attributes of the shaded point, interpolated over the face
+pub struct Fragment {
+    pub world_point: Point3<f64>,
+    // shading normal, the normal map is already applied
+    pub world_normal: Vector3<f64>,
+    // from the point towards the camera
+    pub camera_direction: Vector3<f64>,
+    pub uv: Vector2<f64>,
+    // change of the uv over one pixel in x and y, for sampling the own textures of the shader
+    pub uv_dx: Vector2<f64>,
+    pub uv_dy: Vector2<f64>
+}
+
+// material of the object sampled at the fragment
+pub struct Surface {
+    pub albedo: Color<f64>,
+    pub specular: Color<f64>,
+    pub metallic: f64,
+    pub roughness: f64,
+    pub ambient_occlusion: f64,
+    pub emission: Color<f64>
+}
+
+// the ambient part is kept apart, the screen space ambient occlusion is applied to it later
+pub struct Shaded {
+    pub direct: Color<f64>,
+    pub ambient: Color<f64>
+}
+
+// the lighting of the scene available to the fragment stage
+pub struct ShadingContext<'a> {
+    pub lights: &'a [Option<Light>],
+    pub shadow_map: &'a ShadowMap,
+    // the light casting the shadows, None when the shadows are disabled
+    pub shadow_light_handle: Option<usize>,
+    pub environment: &'a Environment,
+    // used for the ambient light when the environment is disabled
+    pub ambient_light_color: Color<f64>
+}
+
+impl<'a> ShadingContext<'a> {
+    // direction towards every light reaching the point and its color, attenuation and shadow included
+    pub fn incoming_light<'b>(&'b self, world_point: &'b Point3<f64>) -> impl Iterator<Item = (Vector3<f64>, Color<f64>)> + 'b {
+        self.lights.iter().enumerate().filter_map(move |(light_handle, light)| {
+            let light = light.as_ref()?;
+            let (direction, radiance) = light.illuminate(world_point)?;
+            let shadow = if self.shadow_light_handle == Some(light_handle) {
+                self.shadow_map.visibility(&world_point.to_homogeneous())
+            } else {
+                1.
+            };
+            Some((direction, light.color * (radiance * shadow)))
+        })
+    }
+}
+
+pub trait Shader {
+    // color of the fragment; material is the object being drawn for its parameters and textures
+    fn fragment(&self, fragment: &Fragment, surface: &Surface, material: &Object, context: &ShadingContext) -> Shaded;
+}
+
+pub struct PhongShader;
+
+impl Shader for PhongShader {
+    fn fragment(&self, fragment: &Fragment, surface: &Surface, material: &Object, context: &ShadingContext) -> Shaded {
+        let normal = &fragment.world_normal;
+
+        let mut direct = surface.emission;
+        for (light_direction, light_color) in context.incoming_light(&fragment.world_point) {
+            let cos = normal.dot(&light_direction);
+            if cos <= 0. {
+                continue;
+            }
+            let reflection_direction = 2. * cos * normal - light_direction;
+            let gloss = reflection_direction.dot(&fragment.camera_direction).max(0.).powf(material.shininess);
+            direct = direct + light_color * (
+                surface.specular * material.specular_intensity * gloss +
+                surface.albedo * material.diffuse_intensity * cos
+            );
+        }
+
+        let environment = context.environment;
+        let ambient = if environment.enabled {
+            let irradiance = environment.irradiance(normal) * (1. / PI);
+            let n_dot_v = normal.dot(&fragment.camera_direction).max(1e-4);
+            let reflection_direction = 2. * n_dot_v * normal - fragment.camera_direction;
+            let phong_roughness = (2. / (material.shininess + 2.)).sqrt();
+            irradiance * surface.albedo * material.diffuse_intensity +
+            environment.radiance(&reflection_direction, phong_roughness) * surface.specular * material.specular_intensity
+        } else {
+            context.ambient_light_color * material.ambient
+        };
+
+        Shaded { direct, ambient: ambient * surface.ambient_occlusion }
+    }
+}
+
+// metallic-roughness Cook-Torrance
+pub struct PbrShader;
+
+impl Shader for PbrShader {
+    fn fragment(&self, fragment: &Fragment, surface: &Surface, material: &Object, context: &ShadingContext) -> Shaded {
+        let normal = &fragment.world_normal;
+
+        let mut direct = surface.emission;
+        for (light_direction, light_color) in context.incoming_light(&fragment.world_point) {
+            if normal.dot(&light_direction) <= 0. {
+                continue;
+            }
+            direct = direct + light_color * brdf::cook_torrance(
+                normal, &fragment.camera_direction, &light_direction,
+                &surface.albedo, surface.metallic, surface.roughness
+            );
+        }
+
+        let environment = context.environment;
+        let ambient = if environment.enabled {
+            let irradiance = environment.irradiance(normal) * (1. / PI);
+            let n_dot_v = normal.dot(&fragment.camera_direction).max(1e-4);
+            let reflection_direction = 2. * n_dot_v * normal - fragment.camera_direction;
+            let f0 = brdf::base_reflectance(&surface.albedo, surface.metallic);
+            let fresnel = brdf::fresnel_schlick_roughness(n_dot_v, &f0, surface.roughness);
+            let diffuse_part = (Color { r: 1., g: 1., b: 1. } - fresnel) * (1. - surface.metallic);
+            irradiance * diffuse_part * surface.albedo +
+            environment.radiance(&reflection_direction, surface.roughness) * brdf::environment_brdf(&f0, surface.roughness, n_dot_v)
+        } else {
+            context.ambient_light_color * material.ambient * surface.albedo
+        };
+
+        Shaded { direct, ambient: ambient * surface.ambient_occlusion }
+    }
+}
@@ -0,0 +1,171 @@
+use crate::types::*;
+use crate::raster;
+use crate::camera::Camera;
+use crate::object::Object;
+use crate::console::log;
+
+pub struct ShadowMap {
+    pub enabled: bool,
+    pub size: usize,
+    // depth offset against the shadow acne, in the light depth range [0:1]
+    pub bias: f64,
+    // radius (in shadow map texels) of the percentage-closer filtering kernel
+    pub pcf_radius: i32,
+    // allocated by the first render, so the scenes without shadows do not pay for it
+    depth: Vec<f64>,
+    // world space -> shadow map space (x, y in texels, z in [0:1])
+    light_matrix: Matrix4<f64>
+}
+
+impl ShadowMap {
+    pub fn new() -> ShadowMap {
+        ShadowMap {
+            enabled: false,
+            size: 1024,
+            bias: 0.005,
+            pcf_radius: 1,
+            depth: Vec::new(),
+            light_matrix: _one()
+        }
+    }
+
+    pub fn set_param(&mut self, param_id: u32, param_value: f64) {
+        match param_id {
+            1 => self.enabled = param_value != 0.,
+            2 => self.bias = param_value,
+            3 => self.pcf_radius = param_value.max(0.) as i32,
+            4 => {
+                self.size = param_value.max(1.) as usize;
+                self.depth = Vec::new();
+            },
+            _ => log("Not recognized shadow param")
+        }
+    }
+
+    // orthographic view of the light fitted to the bounding sphere of the scene
+    fn fit_light_matrix(&mut self, objects: &[Object], light_direction: &Vector3<f64>) -> bool {
+        let mut min = Vector3::repeat(f64::MAX);
+        let mut max = Vector3::repeat(f64::MIN);
+        for obj in objects.iter() {
+            let to_world = obj.get_to_world_matrix();
+            for vertex in obj.vertices.iter() {
+                let v = (to_world * vertex).xyz();
+                min = min.inf(&v);
+                max = max.sup(&v);
+            }
+        }
+        if min[0] > max[0] {
+            return false;
+        }
+
+        let center = Point3::from((min + max) / 2.);
+        let radius = ((max - min).norm() / 2.).max(1e-6);
+        let direction = light_direction.normalize();
+        let up = if direction[1].abs() > 0.99 { Vector3::new(1., 0., 0.) } else { Vector3::new(0., 1., 0.) };
+        let eye = center - direction * radius * 2.;
+        let look_at = Camera::compute_look_at(&eye, &center, &up);
+
+        // the scene lies between radius and 3 * radius in front of the light
+        let near = radius;
+        let far = radius * 3.;
+        let projection = Matrix4::new(
+            1. / radius, 0.,          0.,                 0.,
+            0.,          1. / radius, 0.,                 0.,
+            0.,          0.,          -1. / (far - near), -near / (far - near),
+            0.,          0.,          0.,                 1.
+        );
+
+        let size = self.size as f64;
+        let to_shadow_map = Matrix4::new(
+            size / 2., 0.,          0., size / 2.,
+            0.,        - size / 2., 0., size / 2.,
+            0.,        0.,          1., 0.,
+            0.,        0.,          0., 1.
+        );
+
+        self.light_matrix = to_shadow_map * projection * look_at;
+        true
+    }
+
+    pub fn render(&mut self, objects: &[Object], light_direction: &Vector3<f64>) {
+        self.depth.clear();
+        self.depth.resize(self.size * self.size, 1.);
+
+        if !self.fit_light_matrix(objects, light_direction) {
+            return;
+        }
+
+        let size = self.size as i32;
+        for obj in objects.iter() {
+            let final_matrix = self.light_matrix * obj.get_to_world_matrix();
+            let light_vertices: Vec<Vertex> = obj.vertices.iter().map(|vertex| final_matrix * vertex).collect();
+
+            for face in obj.faces.iter() {
+                let depth = &mut self.depth;
+                raster::rasterize_face(size, size,
+                    &light_vertices[face.vertices_indexes[0]],
+                    &light_vertices[face.vertices_indexes[1]],
+                    &light_vertices[face.vertices_indexes[2]],
+                    true,
+                    |index, z| {
+                        if z < depth[index] {
+                            depth[index] = z;
+                        }
+                    }
+                );
+            }
+        }
+    }
+
+    // fraction [0:1] of the light reaching the world space point
+    pub fn visibility(&self, world_point: &Vertex) -> f64 {
+        let point = self.light_matrix * world_point;
+        let size = self.size as i32;
+        let x = point[0].round() as i32;
+        let y = point[1].round() as i32;
+        let depth = point[2] - self.bias;
+
+        let mut lit = 0;
+        let mut total = 0;
+        for dy in -self.pcf_radius..=self.pcf_radius {
+            for dx in -self.pcf_radius..=self.pcf_radius {
+                let sx = x + dx;
+                let sy = y + dy;
+                total += 1;
+                if sx < 0 || sx >= size || sy < 0 || sy >= size {
+                    lit += 1;
+                    continue;
+                }
+                if depth <= self.depth[raster::get_index(sy as u32, sx as u32, size as u32)] {
+                    lit += 1;
+                }
+            }
+        }
+        lit as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn point_under_blocker_is_shadowed() {
+        // horizontal square at the height 1 lit from above
+        let mut blocker = Object::new();
+        for (x, z) in [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)] {
+            blocker.add_vertex(x, 1., z);
+        }
+        blocker.add_texture_vertex(0., 0., 0.);
+        blocker.add_vertex_normal(0., 1., 0.);
+        blocker.add_face(0, 0, 0, 1, 0, 0, 2, 0, 0);
+        blocker.add_face(0, 0, 0, 2, 0, 0, 3, 0, 0);
+
+        let mut shadow_map = ShadowMap::new();
+        shadow_map.render(&[blocker], &Vector3::new(0., -1., 0.));
+
+        assert_eq!(shadow_map.visibility(&Vertex::new(0., 0., 0., 1.)), 0.);
+        assert_eq!(shadow_map.visibility(&Vertex::new(0., 1.5, 0., 1.)), 1.);
+        assert_eq!(shadow_map.visibility(&Vertex::new(3., 0., 0., 1.)), 1.);
+    }
+}
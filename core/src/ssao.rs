@@ -0,0 +1,223 @@
+use crate::types::*;
+use crate::console::log;
+
+const NOISE_SIZE: usize = 4;
+// the bilateral blur removing the noise pattern covers exactly one noise tile, offsets -2..=1
+const BLUR_OFFSETS: std::ops::RangeInclusive<i32> = -(NOISE_SIZE as i32 / 2)..=(NOISE_SIZE as i32 / 2 - 1);
+
+// deterministic pseudo random numbers in [0:1), the kernel is the same from frame to frame
+fn random(seed: &mut u32) -> f64 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 17;
+    *seed ^= *seed << 5;
+    *seed as f64 / (u32::MAX as f64 + 1.)
+}
+
+pub struct Ssao {
+    pub enabled: bool,
+    // view space radius of the sampled hemisphere
+    pub radius: f64,
+    pub strength: f64,
+    pub sample_count: usize,
+    // view space depth offset against the self occlusion of flat surfaces
+    pub bias: f64,
+    // tangent space hemisphere (z is the normal), denser towards the center
+    kernel: Vec<Vector3<f64>>,
+    // tiled random rotations of the kernel around the normal
+    noise: Vec<Vector3<f64>>,
+    // view space z of every pixel reconstructed from the depth buffer
+    view_z: Vec<f64>,
+    occlusion: Vec<f64>,
+    blurred: Vec<f64>
+}
+
+impl Ssao {
+    pub fn new() -> Ssao {
+        let mut seed = 0x9e37_79b9;
+        let noise = (0..NOISE_SIZE * NOISE_SIZE).map(|_| {
+            Vector3::new(random(&mut seed) * 2. - 1., random(&mut seed) * 2. - 1., 0.)
+        }).collect();
+
+        let mut ssao = Ssao {
+            enabled: false,
+            radius: 0.5,
+            strength: 1.,
+            sample_count: 16,
+            bias: 0.025,
+            kernel: Vec::new(),
+            noise,
+            view_z: Vec::new(),
+            occlusion: Vec::new(),
+            blurred: Vec::new()
+        };
+        ssao.generate_kernel();
+        ssao
+    }
+
+    pub fn set_param(&mut self, param_id: u32, param_value: f64) {
+        match param_id {
+            1 => self.enabled = param_value != 0.,
+            2 => self.radius = param_value.max(1e-3),
+            3 => self.strength = param_value.max(0.),
+            4 => {
+                self.sample_count = (param_value.max(1.) as usize).min(256);
+                self.generate_kernel();
+            },
+            5 => self.bias = param_value.max(0.),
+            _ => log("Not recognized ssao param")
+        }
+    }
+
+    fn generate_kernel(&mut self) {
+        let mut seed = 0x2545_f491;
+        let count = self.sample_count;
+        self.kernel = (0..count).map(|i| {
+            let sample = Vector3::new(
+                random(&mut seed) * 2. - 1.,
+                random(&mut seed) * 2. - 1.,
+                random(&mut seed)
+            ).try_normalize(1e-6).unwrap_or_else(|| Vector3::new(0., 0., 1.));
+            let scale = i as f64 / count as f64;
+            sample * random(&mut seed) * (0.1 + 0.9 * scale * scale)
+        }).collect();
+    }
+
+    // ambient visibility of the pixel computed by the last pass, 1 - not occluded
+    pub fn visibility(&self, pixel_index: usize) -> f64 {
+        self.blurred.get(pixel_index).copied().unwrap_or(1.)
+    }
+
+    // z_buf holds the screen space depth (1 - empty), normals are in the view space;
+    // to_screen is the projection followed by the viewport transform
+    pub fn compute(&mut self, z_buf: &[f64], normals: &[Vector3<f64>], width: usize, height: usize, to_screen: &Matrix4<f64>) {
+        let to_view = match to_screen.try_inverse() {
+            Some(matrix) => matrix,
+            None => return
+        };
+        self.view_z.resize(width * height, 0.);
+        self.occlusion.resize(width * height, 1.);
+        self.blurred.resize(width * height, 1.);
+
+        let view_position = |x: f64, y: f64, depth: f64| {
+            let position = to_view * Vector4::new(x, y, depth, 1.);
+            Vector3::new(position[0], position[1], position[2]) / position[3]
+        };
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                self.view_z[index] = view_position(x as f64, y as f64, z_buf[index])[2];
+            }
+        }
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                if z_buf[index] == 1. {
+                    self.occlusion[index] = 1.;
+                    continue;
+                }
+
+                let position = view_position(x as f64, y as f64, z_buf[index]);
+                let normal = normals[index];
+
+                // gram-schmidt of the random vector gives the rotated tangent frame
+                let random_vector = self.noise[(y % NOISE_SIZE) * NOISE_SIZE + x % NOISE_SIZE];
+                let tangent = match (random_vector - normal * random_vector.dot(&normal)).try_normalize(1e-6) {
+                    Some(tangent) => tangent,
+                    None => normal.cross(&Vector3::new(0., 1., 0.)).try_normalize(1e-6).unwrap_or_else(|| Vector3::new(1., 0., 0.))
+                };
+                let bitangent = normal.cross(&tangent);
+
+                let mut occlusion = 0.;
+                for kernel_sample in self.kernel.iter() {
+                    let sample = position + (tangent * kernel_sample[0] + bitangent * kernel_sample[1] + normal * kernel_sample[2]) * self.radius;
+
+                    let screen = to_screen * Vector4::new(sample[0], sample[1], sample[2], 1.);
+                    if screen[3] == 0. {
+                        continue;
+                    }
+                    let (sx, sy) = ((screen[0] / screen[3]).round(), (screen[1] / screen[3]).round());
+                    if sx < 0. || sy < 0. || sx >= width as f64 || sy >= height as f64 {
+                        continue;
+                    }
+                    let scene_depth = z_buf[sy as usize * width + sx as usize];
+                    if scene_depth == 1. {
+                        continue;
+                    }
+                    let scene_z = self.view_z[sy as usize * width + sx as usize];
+
+                    // the camera looks along -z, so the closer surface has the bigger z
+                    if scene_z >= sample[2] + self.bias {
+                        // the geometry far in front of the point does not occlude it
+                        let range = (self.radius / (position[2] - scene_z).abs().max(1e-6)).min(1.);
+                        occlusion += range * range * (3. - 2. * range);
+                    }
+                }
+
+                let occlusion = occlusion / self.kernel.len().max(1) as f64;
+                self.occlusion[index] = (1. - occlusion * self.strength).clamp(0., 1.);
+            }
+        }
+
+        self.blur(z_buf, width, height);
+    }
+
+    // depth-aware box blur over the size of the noise tile, the edges of the objects are kept
+    fn blur(&mut self, z_buf: &[f64], width: usize, height: usize) {
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                if z_buf[index] == 1. {
+                    self.blurred[index] = 1.;
+                    continue;
+                }
+                let center_z = self.view_z[index];
+
+                let mut sum = 0.;
+                let mut total_weight = 0.;
+                for offset_y in BLUR_OFFSETS {
+                    for offset_x in BLUR_OFFSETS {
+                        let (sx, sy) = (x as i32 + offset_x, y as i32 + offset_y);
+                        if sx < 0 || sy < 0 || sx >= width as i32 || sy >= height as i32 {
+                            continue;
+                        }
+                        let sample_index = sy as usize * width + sx as usize;
+                        if z_buf[sample_index] == 1. {
+                            continue;
+                        }
+                        let difference = (self.view_z[sample_index] - center_z) / self.radius;
+                        let weight = 1. / (1. + difference * difference * 100.);
+                        sum += self.occlusion[sample_index] * weight;
+                        total_weight += weight;
+                    }
+                }
+                self.blurred[index] = if total_weight > 0. { sum / total_weight } else { 1. };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blur_removes_noise_pattern() {
+        let mut ssao = Ssao::new();
+        let (width, height) = (12, 12);
+        ssao.view_z = vec![-2.; width * height];
+        ssao.blurred = vec![1.; width * height];
+        // a different value for every place in the noise tile
+        ssao.occlusion = (0..width * height)
+            .map(|index| ((index % width) % NOISE_SIZE * NOISE_SIZE + (index / width) % NOISE_SIZE) as f64 / 16.)
+            .collect();
+        ssao.blur(&vec![0.5; width * height], width, height);
+
+        let center = ssao.visibility(6 * width + 6);
+        for y in 2..height - 1 {
+            for x in 2..width - 1 {
+                assert!((ssao.visibility(y * width + x) - center).abs() < 1e-12);
+            }
+        }
+    }
+}
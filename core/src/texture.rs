@@ -1,18 +1,223 @@
+use crate::types::*;
 
-pub struct Texture<T> {
+#[derive(Copy, Clone, PartialEq)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+    Trilinear
+}
+
+impl TextureFilter {
+    pub fn from_id(filter_id: u32) -> Option<TextureFilter> {
+        match filter_id {
+            0 => Some(TextureFilter::Nearest),
+            1 => Some(TextureFilter::Bilinear),
+            2 => Some(TextureFilter::Trilinear),
+            _ => None
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum TextureWrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    ClampToBorder
+}
+
+impl TextureWrap {
+    pub fn from_id(wrap_id: u32) -> Option<TextureWrap> {
+        match wrap_id {
+            0 => Some(TextureWrap::Repeat),
+            1 => Some(TextureWrap::MirroredRepeat),
+            2 => Some(TextureWrap::ClampToEdge),
+            3 => Some(TextureWrap::ClampToBorder),
+            _ => None
+        }
+    }
+
+    // maps the texel coordinate into [0:size), None stands for the border
+    fn apply(self, coord: i64, size: usize) -> Option<usize> {
+        let size = size as i64;
+        match self {
+            TextureWrap::Repeat => Some(coord.rem_euclid(size) as usize),
+            TextureWrap::MirroredRepeat => {
+                let mirrored = coord.rem_euclid(2 * size);
+                if mirrored >= size {
+                    Some((2 * size - 1 - mirrored) as usize)
+                } else {
+                    Some(mirrored as usize)
+                }
+            },
+            TextureWrap::ClampToEdge => Some(coord.clamp(0, size - 1) as usize),
+            TextureWrap::ClampToBorder => {
+                if coord < 0 || coord >= size { None } else { Some(coord as usize) }
+            }
+        }
+    }
+}
+
+// how the 8 bit texture data is encoded
+#[derive(Copy, Clone, PartialEq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear
+}
+
+impl ColorSpace {
+    pub fn decode(self, value: u8) -> f64 {
+        match self {
+            ColorSpace::Srgb => srgb_to_linear(value),
+            ColorSpace::Linear => value as f64 / 255.
+        }
+    }
+
+    pub fn decode_color(self, color: &Color<u8>) -> Color<f64> {
+        Color {
+            r: self.decode(color.r),
+            g: self.decode(color.g),
+            b: self.decode(color.b)
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Sampler {
+    pub filter: TextureFilter,
+    pub wrap_u: TextureWrap,
+    pub wrap_v: TextureWrap,
+    pub border_color: Color<f64>,
+    // uv transform: scale, then rotation, then offset
+    pub uv_offset: Vector2<f64>,
+    pub uv_scale: Vector2<f64>,
+    pub uv_rotation: f64
+}
+
+impl Sampler {
+    pub fn new() -> Sampler {
+        Sampler {
+            filter: TextureFilter::Trilinear,
+            wrap_u: TextureWrap::Repeat,
+            wrap_v: TextureWrap::Repeat,
+            border_color: Color { r: 0., g: 0., b: 0. },
+            uv_offset: Vector2::new(0., 0.),
+            uv_scale: Vector2::new(1., 1.),
+            uv_rotation: 0.
+        }
+    }
+
+    // transforms the uv direction (derivative), offset is not applied
+    fn transform_uv_direction(&self, u: f64, v: f64) -> (f64, f64) {
+        let u = u * self.uv_scale[0];
+        let v = v * self.uv_scale[1];
+        let sin = self.uv_rotation.sin();
+        let cos = self.uv_rotation.cos();
+        (u * cos - v * sin, u * sin + v * cos)
+    }
+
+    fn transform_uv(&self, u: f64, v: f64) -> (f64, f64) {
+        let (u, v) = self.transform_uv_direction(u, v);
+        (u + self.uv_offset[0], v + self.uv_offset[1])
+    }
+}
+
+// value which can be stored in a texture and mixed by the sampler
+pub trait Texel: Clone {
+    fn blend(texels: [&Self; 4], weights: [f64; 4]) -> Self;
+    // color in [0:1] range, used for the border of the texture
+    fn from_color(color: &Color<f64>) -> Self;
+}
+
+impl Texel for Pixel {
+    fn blend(texels: [&Self; 4], weights: [f64; 4]) -> Self {
+        let mut color = Color { r: 0., g: 0., b: 0. };
+        let mut a = 0.;
+        for (texel, weight) in texels.iter().zip(weights.iter()) {
+            color = color + texel.color.to_f64() * *weight;
+            a += texel.a as f64 * weight;
+        }
+        Pixel {
+            color: Color {
+                r: color.r.round() as u8,
+                g: color.g.round() as u8,
+                b: color.b.round() as u8
+            },
+            a: a.round() as u8
+        }
+    }
+
+    fn from_color(color: &Color<f64>) -> Self {
+        Pixel {
+            color: (*color * 255.).to_u8(),
+            a: 255
+        }
+    }
+}
+
+impl Texel for Color<f64> {
+    fn blend(texels: [&Self; 4], weights: [f64; 4]) -> Self {
+        let mut color = Color { r: 0., g: 0., b: 0. };
+        for (texel, weight) in texels.iter().zip(weights.iter()) {
+            color = color + **texel * *weight;
+        }
+        color
+    }
+
+    fn from_color(color: &Color<f64>) -> Self {
+        *color
+    }
+}
+
+impl Texel for f64 {
+    fn blend(texels: [&Self; 4], weights: [f64; 4]) -> Self {
+        texels.iter().zip(weights.iter()).map(|(texel, weight)| **texel * weight).sum()
+    }
+
+    fn from_color(color: &Color<f64>) -> Self {
+        color.r
+    }
+}
+
+impl Texel for Vector4<f64> {
+    fn blend(texels: [&Self; 4], weights: [f64; 4]) -> Self {
+        let mut vector = Vector4::zeros();
+        for (texel, weight) in texels.iter().zip(weights.iter()) {
+            vector += **texel * *weight;
+        }
+        vector
+    }
+
+    fn from_color(color: &Color<f64>) -> Self {
+        Vector4::new(color.r * 2. - 1., color.g * 2. - 1., color.b * 2. - 1., 0.)
+    }
+}
+
+pub struct MipLevel<T> {
     pub width: usize,
     pub height: usize,
     pub data: Vec<T>
 }
 
+pub struct Texture<T> {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<T>,
+    // levels starting from the half of the base size, the base level is `data`
+    pub mips: Vec<MipLevel<T>>,
+    pub sampler: Sampler
+}
+
 impl<T> Texture<T>
-where T : Clone
+where T : Texel
 {
     pub fn new() -> Texture<T> {
         Texture {
             width: 0,
             height: 0,
-            data: Vec::new()
+            data: Vec::new(),
+            mips: Vec::new(),
+            sampler: Sampler::new()
         }
     }
 
@@ -20,21 +225,148 @@ where T : Clone
         self.width = width;
         self.height = height;
         self.data.resize(width * height, init_value);
+        self.mips.clear();
     }
 
     pub fn get_data_pointer(&self) -> *const T {
         self.data.as_ptr()
     }
 
-    pub fn get_pixel(&self, mut u: f64, mut v: f64) -> &T {
-    // TODO: this function sucks because of these ifs
+    // has to be called each time the base level data is changed
+    pub fn generate_mips(&mut self) {
+        self.mips.clear();
+
+        let mut width = self.width;
+        let mut height = self.height;
+        while width > 1 || height > 1 {
+            let (src_width, src_height, src) = self.level(self.mips.len());
+            let mip_width = (width / 2).max(1);
+            let mip_height = (height / 2).max(1);
+
+            let mut data = Vec::with_capacity(mip_width * mip_height);
+            for y in 0..mip_height {
+                for x in 0..mip_width {
+                    let x0 = (x * 2).min(src_width - 1);
+                    let x1 = (x * 2 + 1).min(src_width - 1);
+                    let y0 = (y * 2).min(src_height - 1);
+                    let y1 = (y * 2 + 1).min(src_height - 1);
+                    data.push(T::blend(
+                        [
+                            &src[y0 * src_width + x0], &src[y0 * src_width + x1],
+                            &src[y1 * src_width + x0], &src[y1 * src_width + x1]
+                        ],
+                        [0.25, 0.25, 0.25, 0.25]
+                    ));
+                }
+            }
+
+            self.mips.push(MipLevel { width: mip_width, height: mip_height, data });
+            width = mip_width;
+            height = mip_height;
+        }
+    }
+
+    fn level(&self, level: usize) -> (usize, usize, &[T]) {
+        if level == 0 {
+            (self.width, self.height, &self.data)
+        } else {
+            let mip = &self.mips[level - 1];
+            (mip.width, mip.height, &mip.data)
+        }
+    }
+
+    fn fetch(&self, data: &[T], width: usize, height: usize, x: i64, y: i64) -> T {
+        // nothing uploaded yet, the wraps need at least one texel
+        if width == 0 || height == 0 {
+            return T::from_color(&self.sampler.border_color);
+        }
+        match (self.sampler.wrap_u.apply(x, width), self.sampler.wrap_v.apply(y, height)) {
+            (Some(x), Some(y)) => data[y * width + x].clone(),
+            _ => T::from_color(&self.sampler.border_color)
+        }
+    }
+
     // u [0:1] stands for x; v [0:1] stands for y
-        if u <= 0. { u = 0.001 }
-        if v <= 0. { v = 0.001 }
-        if u >= 1. { u = 0.999 }
-        if v >= 1. { v = 0.999 }
-        let x = (u * (self.width as f64)) as usize;
-        let y = ((1. - v) * (self.height as f64)) as usize;
-        &self.data[y * self.width + x]
-    }
-}
\ No newline at end of file
+    fn sample_nearest(&self, level: usize, u: f64, v: f64) -> T {
+        let (width, height, data) = self.level(level);
+        let x = (u * width as f64).floor() as i64;
+        let y = ((1. - v) * height as f64).floor() as i64;
+        self.fetch(data, width, height, x, y)
+    }
+
+    fn sample_bilinear(&self, level: usize, u: f64, v: f64) -> T {
+        let (width, height, data) = self.level(level);
+        // texel centers are at the half of the texel
+        let x = u * width as f64 - 0.5;
+        let y = (1. - v) * height as f64 - 0.5;
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+        let x0 = x0 as i64;
+        let y0 = y0 as i64;
+
+        T::blend(
+            [
+                &self.fetch(data, width, height, x0, y0),
+                &self.fetch(data, width, height, x0 + 1, y0),
+                &self.fetch(data, width, height, x0, y0 + 1),
+                &self.fetch(data, width, height, x0 + 1, y0 + 1)
+            ],
+            [(1. - tx) * (1. - ty), tx * (1. - ty), (1. - tx) * ty, tx * ty]
+        )
+    }
+
+    // lod is the mip level to be used, fractional part is used by trilinear filtering
+    pub fn sample(&self, u: f64, v: f64, lod: f64) -> T {
+        let (u, v) = self.sampler.transform_uv(u, v);
+        let lod = lod.clamp(0., self.mips.len() as f64);
+        match self.sampler.filter {
+            TextureFilter::Nearest => self.sample_nearest(0, u, v),
+            TextureFilter::Bilinear => self.sample_bilinear(lod.round() as usize, u, v),
+            TextureFilter::Trilinear => {
+                let level = lod.floor() as usize;
+                let t = lod - level as f64;
+                if t == 0. || level == self.mips.len() {
+                    return self.sample_bilinear(level, u, v);
+                }
+                let near = self.sample_bilinear(level, u, v);
+                let far = self.sample_bilinear(level + 1, u, v);
+                T::blend([&near, &far, &near, &far], [1. - t, t, 0., 0.])
+            }
+        }
+    }
+
+    // samples the texture choosing the mip level from the screen-space derivatives of the uv
+    pub fn sample_grad(&self, u: f64, v: f64, du_dx: f64, dv_dx: f64, du_dy: f64, dv_dy: f64) -> T {
+        let (du_dx, dv_dx) = self.sampler.transform_uv_direction(du_dx, dv_dx);
+        let (du_dy, dv_dy) = self.sampler.transform_uv_direction(du_dy, dv_dy);
+        let width = self.width as f64;
+        let height = self.height as f64;
+        let rho_x = ((du_dx * width).powi(2) + (dv_dx * height).powi(2)).sqrt();
+        let rho_y = ((du_dy * width).powi(2) + (dv_dy * height).powi(2)).sqrt();
+        let rho = rho_x.max(rho_y);
+        let lod = if rho > 1. { rho.log2() } else { 0. };
+        self.sample(u, v, lod)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_texture_samples_border() {
+        let mut texture: Texture<Color<f64>> = Texture::new();
+        texture.sampler.border_color = Color { r: 0.25, g: 0.25, b: 0.25 };
+        for wrap in [TextureWrap::Repeat, TextureWrap::MirroredRepeat, TextureWrap::ClampToEdge, TextureWrap::ClampToBorder] {
+            for filter in [TextureFilter::Nearest, TextureFilter::Bilinear, TextureFilter::Trilinear] {
+                texture.sampler.wrap_u = wrap;
+                texture.sampler.wrap_v = wrap;
+                texture.sampler.filter = filter;
+                let color = texture.sample(0.3, 0.7, 2.);
+                assert_eq!((color.r, color.g, color.b), (0.25, 0.25, 0.25));
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::types::*;
+use crate::console::log;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ToneMapping {
+    None,
+    Reinhard,
+    ReinhardExtended,
+    // Narkowicz fit of the ACES filmic curve
+    AcesFilmic,
+    // minimal AgX approximation (Wrensch)
+    AgX
+}
+
+impl ToneMapping {
+    pub fn from_id(operator_id: u32) -> Option<ToneMapping> {
+        match operator_id {
+            0 => Some(ToneMapping::None),
+            1 => Some(ToneMapping::Reinhard),
+            2 => Some(ToneMapping::ReinhardExtended),
+            3 => Some(ToneMapping::AcesFilmic),
+            4 => Some(ToneMapping::AgX),
+            _ => None
+        }
+    }
+}
+
+pub struct ToneMapper {
+    pub operator: ToneMapping,
+    pub exposure: f64,
+    // plain gamma encoding, only used when the srgb output is disabled
+    pub gamma: f64,
+    // encode the output with the srgb transfer function
+    pub srgb_output: bool,
+    // the smallest value mapped to white by the extended reinhard
+    pub white_point: f64
+}
+
+fn mul_columns(columns: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    let mut result = [0.; 3];
+    for (column, value) in columns.iter().zip(v.iter()) {
+        for (result, element) in result.iter_mut().zip(column.iter()) {
+            *result += element * value;
+        }
+    }
+    result
+}
+
+const AGX_INSET: [[f64; 3]; 3] = [
+    [0.842479062253094, 0.0423282422610123, 0.0423756549057051],
+    [0.0784335999999992, 0.878468636469772, 0.0784336],
+    [0.0792237451477643, 0.0791661274605434, 0.879142973793104]
+];
+
+const AGX_OUTSET: [[f64; 3]; 3] = [
+    [1.19687900512017, -0.0528968517574562, -0.0529716355144438],
+    [-0.0980208811401368, 1.15190312990417, -0.0980434501171241],
+    [-0.0990297440797205, -0.0989611768448433, 1.15107367264116]
+];
+
+fn agx_contrast(x: f64) -> f64 {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232
+}
+
+fn agx(color: Color<f64>) -> Color<f64> {
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    let inset = mul_columns(&AGX_INSET, [color.r, color.g, color.b]);
+    let encoded = inset.map(|value| {
+        let log = value.max(1e-10).log2().clamp(min_ev, max_ev);
+        agx_contrast((log - min_ev) / (max_ev - min_ev))
+    });
+    // the curve produces display encoded values, they are linearized back
+    let outset = mul_columns(&AGX_OUTSET, encoded).map(|value| value.max(0.).powf(2.2));
+    Color { r: outset[0], g: outset[1], b: outset[2] }
+}
+
+fn aces_filmic(x: f64) -> f64 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+impl ToneMapper {
+    pub fn new() -> ToneMapper {
+        ToneMapper {
+            operator: ToneMapping::None,
+            exposure: 1.,
+            gamma: 2.2,
+            srgb_output: true,
+            white_point: 4.
+        }
+    }
+
+    pub fn set_param(&mut self, param_id: u32, param_value: f64) {
+        match param_id {
+            1 => self.exposure = param_value,
+            2 => self.gamma = param_value.max(1e-3),
+            3 => self.white_point = param_value.max(1e-3),
+            4 => self.srgb_output = param_value != 0.,
+            _ => log("Not recognized tone mapping param")
+        }
+    }
+
+    // maps the linear hdr color into the displayable [0:1] range
+    pub fn map(&self, color: Color<f64>) -> Color<f64> {
+        let color = color * self.exposure;
+        let white = self.white_point * self.white_point;
+        let per_channel = |f: &dyn Fn(f64) -> f64| Color { r: f(color.r), g: f(color.g), b: f(color.b) };
+        let mapped = match self.operator {
+            ToneMapping::None => color,
+            ToneMapping::Reinhard => per_channel(&|c| c / (1. + c)),
+            ToneMapping::ReinhardExtended => per_channel(&|c| c * (1. + c / white) / (1. + c)),
+            ToneMapping::AcesFilmic => per_channel(&aces_filmic),
+            ToneMapping::AgX => agx(color)
+        };
+        Color {
+            r: mapped.r.clamp(0., 1.),
+            g: mapped.g.clamp(0., 1.),
+            b: mapped.b.clamp(0., 1.)
+        }
+    }
+
+    // the only place where the linear color is encoded for the display
+    pub fn apply(&self, color: Color<f64>) -> Color<u8> {
+        let mapped = self.map(color);
+        let inverse_gamma = 1. / self.gamma;
+        let encode = |c: f64| if self.srgb_output { linear_to_srgb(c) } else { c.powf(inverse_gamma) };
+        Color {
+            r: (encode(mapped.r) * 255.).round() as u8,
+            g: (encode(mapped.g) * 255.).round() as u8,
+            b: (encode(mapped.b) * 255.).round() as u8
+        }
+    }
+}
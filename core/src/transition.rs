@@ -0,0 +1,56 @@
+use crate::camera::{Camera, CameraState};
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Easing {
+    Linear,
+    // smooth start and stop
+    EaseInOut,
+    // slower start and stop, faster middle
+    EaseInOutCubic
+}
+
+impl Easing {
+    pub fn from_id(easing_id: u32) -> Option<Easing> {
+        match easing_id {
+            0 => Some(Easing::Linear),
+            1 => Some(Easing::EaseInOut),
+            2 => Some(Easing::EaseInOutCubic),
+            _ => None
+        }
+    }
+
+    pub fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseInOut => t * t * (3. - 2. * t),
+            Easing::EaseInOutCubic => if t < 0.5 {
+                4. * t * t * t
+            } else {
+                1. - (2. - 2. * t).powi(3) / 2.
+            }
+        }
+    }
+}
+
+// moves the camera between two states over the duration, driven by the frame time
+pub struct CameraTransition {
+    from: CameraState,
+    to: CameraState,
+    duration: f64,
+    easing: Easing,
+    elapsed: f64
+}
+
+impl CameraTransition {
+    pub fn new(from: CameraState, to: CameraState, duration: f64, easing: Easing) -> CameraTransition {
+        CameraTransition { from, to, duration, easing, elapsed: 0. }
+    }
+
+    // returns true when the camera has arrived
+    pub fn update(&mut self, dt: f64, camera: &mut Camera) -> bool {
+        self.elapsed += dt;
+        let t = if self.duration > 0. { (self.elapsed / self.duration).min(1.) } else { 1. };
+        camera.set_state(&self.from.interpolate(&self.to, self.easing.apply(t)));
+        t >= 1.
+    }
+}
@@ -1,9 +1,10 @@
 pub use nalgebra::{
-    Vector3, Vector4, Matrix4,
-    Point3
+    Vector2, Vector3, Vector4, Matrix4,
+    Point3, Unit, UnitQuaternion
 };
 
 use std::ops;
+use std::sync::OnceLock;
 
 #[derive(Copy, Clone)]
 pub struct Pixel {
@@ -63,6 +64,24 @@ impl Color<f64> {
             b: self.b as u8,
         }
     }
+
+    pub fn to_f32(self) -> Color<f32> {
+        Color {
+            r: self.r as f32,
+            g: self.g as f32,
+            b: self.b as f32,
+        }
+    }
+}
+
+impl Color<f32> {
+    pub fn to_f64(self) -> Color<f64> {
+        Color {
+            r: self.r as f64,
+            g: self.g as f64,
+            b: self.b as f64,
+        }
+    }
 }
 
 impl ops::Add<Color<f64>> for Color<f64> {
@@ -101,6 +120,18 @@ impl ops::Neg for Color<f64> {
     }
 }
 
+impl ops::Mul<Color<f64>> for Color<f64> {
+    type Output = Color<f64>;
+
+    fn mul(self, mul: Color<f64>) -> Color<f64> {
+        Color {
+            r: self.r * mul.r,
+            g: self.g * mul.g,
+            b: self.b * mul.b,
+        }
+    }
+}
+
 impl ops::Mul<f64> for Color<f64> {
     type Output = Color<f64>;
 
@@ -123,4 +154,27 @@ pub const black_color: Color<u8> = Color {
     r: 0,
     g: 0,
     b: 0
-};
\ No newline at end of file
+};
+
+static SRGB_TO_LINEAR: OnceLock<[f64; 256]> = OnceLock::new();
+
+// decodes the 8 bit srgb encoded value into the linear [0:1] range
+pub fn srgb_to_linear(value: u8) -> f64 {
+    SRGB_TO_LINEAR.get_or_init(|| {
+        let mut table = [0.; 256];
+        for (encoded, linear) in table.iter_mut().enumerate() {
+            let c = encoded as f64 / 255.;
+            *linear = if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) };
+        }
+        table
+    })[value as usize]
+}
+
+// encodes the linear [0:1] value with the srgb transfer function
+pub fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    }
+}
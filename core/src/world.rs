@@ -1,11 +1,26 @@
-use crate::object::Object;
+use std::rc::Rc;
+
+use crate::object::{Object, ShadingModel};
+use crate::shader::Shader;
+use crate::bvh::Hit;
+use crate::bounds::Aabb;
+use crate::light::{Light, LightKind};
+use crate::environment::Environment;
+use crate::background::Background;
+use crate::fog::Fog;
 use crate::types::*;
+use crate::texture::{TextureFilter, TextureWrap, ColorSpace};
 
 pub struct World {
     pub objects: Vec<Object>,
-    pub direct_light_direction: Vector4<f64>,
-    pub direct_light_color: Color<f64>,
+    // removed lights leave None so the handles of the others stay valid
+    pub lights: Vec<Option<Light>>,
     pub background_light_color: Color<f64>,
+    // image based lighting, replaces the background light when enabled
+    pub environment: Environment,
+    // what is shown where there is no geometry
+    pub background: Background,
+    pub fog: Fog,
     // pub ambient_coeff: f64,
     // pub diffuse_coeff: f64,
     // pub specular_coeff: f64
@@ -15,9 +30,11 @@ impl World {
     pub fn new() -> World {
         World {
             objects: Vec::new(),
-            direct_light_direction: Vector4::new(-1., -1., -1., 0.).normalize(),
-            direct_light_color: Color{ r: 1., g: 1., b: 1.},
+            lights: vec![Some(Light::new(LightKind::Directional))],
             background_light_color: Color{ r: 1., g: 1., b: 1.},
+            environment: Environment::new(),
+            background: Background::new(),
+            fog: Fog::new(),
             // ambient_coeff: 0.1,
             // diffuse_coeff: 0.5,
             // specular_coeff: 1.
@@ -25,7 +42,53 @@ impl World {
     }
 
     fn is_handle_exist(&self, object_handle: usize) -> bool {
-        return object_handle <= self.objects.len()
+        return object_handle < self.objects.len()
+    }
+
+    fn get_light_mut(&mut self, light_handle: usize) -> Option<&mut Light> {
+        self.lights.get_mut(light_handle).and_then(|light| light.as_mut())
+    }
+
+    pub fn add_light(&mut self, kind: LightKind) -> u32 {
+        self.lights.push(Some(Light::new(kind)));
+        (self.lights.len() - 1) as u32
+    }
+
+    pub fn remove_light(&mut self, light_handle: usize) {
+        if light_handle < self.lights.len() {
+            self.lights[light_handle] = None;
+        }
+    }
+
+    pub fn set_light_position(&mut self, light_handle: usize, x: f64, y: f64, z: f64) {
+        if let Some(light) = self.get_light_mut(light_handle) {
+            light.position = Point3::new(x, y, z);
+        }
+    }
+
+    pub fn set_light_direction(&mut self, light_handle: usize, x: f64, y: f64, z: f64) {
+        if let Some(light) = self.get_light_mut(light_handle) {
+            light.direction = Vector3::new(x, y, z).normalize();
+        }
+    }
+
+    pub fn set_light_color(&mut self, light_handle: usize, r: f64, g: f64, b: f64) {
+        if let Some(light) = self.get_light_mut(light_handle) {
+            light.color = Color { r, g, b };
+        }
+    }
+
+    pub fn set_light_param(&mut self, light_handle: usize, param_id: u32, param_value: f64) {
+        if let Some(light) = self.get_light_mut(light_handle) {
+            light.set_param(param_id, param_value);
+        }
+    }
+
+    // the first directional light casts the shadows
+    pub fn get_shadow_light_handle(&self) -> Option<usize> {
+        self.lights.iter().position(|light| {
+            matches!(light, Some(light) if light.kind == LightKind::Directional)
+        })
     }
 
     pub fn new_object(&mut self) -> u32 {
@@ -35,6 +98,33 @@ impl World {
         (self.objects.len() - 1) as u32
     }
 
+    // nearest object hit by the ray, with the handle of the object
+    pub fn raycast(&self, origin: &Point3<f64>, direction: &Vector3<f64>) -> Option<(usize, Hit)> {
+        let direction = direction.try_normalize(1e-12)?;
+        let mut nearest: Option<(usize, Hit)> = None;
+        for (object_handle, obj) in self.objects.iter().enumerate() {
+            if let Some(hit) = obj.raycast(origin, &direction) {
+                if nearest.as_ref().is_none_or(|(_, nearest_hit)| hit.distance < nearest_hit.distance) {
+                    nearest = Some((object_handle, hit));
+                }
+            }
+        }
+        nearest
+    }
+
+    // bounding box of all the objects in the world space
+    pub fn bounds(&self) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for obj in self.objects.iter() {
+            bounds.merge(&obj.world_bounds());
+        }
+        bounds
+    }
+
+    pub fn object_bounds(&self, object_handle: usize) -> Option<Aabb> {
+        self.objects.get(object_handle).map(|obj| obj.world_bounds())
+    }
+
     pub fn add_object_vertex(&mut self, object_handle: usize, x:f64, y:f64, z:f64) {
         if !self.is_handle_exist(object_handle) { return }
 
@@ -83,6 +173,31 @@ impl World {
         self.objects[object_handle].set_color(r, g, b);
     }
 
+    pub fn set_object_shading_model(&mut self, object_handle: usize, model: ShadingModel) {
+        if !self.is_handle_exist(object_handle) { return }
+
+        self.objects[object_handle].shader = model.shader();
+    }
+
+    // custom looks: any implementation of the shader can be assigned to the object
+    pub fn set_object_shader(&mut self, object_handle: usize, shader: Rc<dyn Shader>) {
+        if !self.is_handle_exist(object_handle) { return }
+
+        self.objects[object_handle].shader = shader;
+    }
+
+    pub fn set_object_metallic(&mut self, object_handle: usize, metallic: f64) {
+        if !self.is_handle_exist(object_handle) { return }
+
+        self.objects[object_handle].metallic = metallic.clamp(0., 1.);
+    }
+
+    pub fn set_object_roughness(&mut self, object_handle: usize, roughness: f64) {
+        if !self.is_handle_exist(object_handle) { return }
+
+        self.objects[object_handle].roughness = roughness.clamp(0., 1.);
+    }
+
     pub fn set_object_texture_size(&mut self, object_handle: usize, texture_index: usize, width: usize, height: usize) {
         if !self.is_handle_exist(object_handle) { return }
 
@@ -92,17 +207,89 @@ impl World {
     pub fn set_object_use_texture(&mut self, object_handle: usize, texture_index: usize, value: bool) {
         if !self.is_handle_exist(object_handle) { return }
 
+        let obj = &mut self.objects[object_handle];
         match texture_index {
-            1 => self.objects[object_handle].use_diffuse_texture = value,
-            2 => {
-                self.objects[object_handle].use_normal_texture = value;
-                if value {
-                    self.objects[object_handle].normalize_normal_texture();
-                }
-            },
-            3 => self.objects[object_handle].use_specular_texture = value,
-            4 => self.objects[object_handle].use_emission_texture = value,
-            _ => {}
+            1 => obj.use_diffuse_texture = value,
+            2 => obj.use_normal_texture = value,
+            3 => obj.use_specular_texture = value,
+            4 => obj.use_emission_texture = value,
+            5 => obj.use_metallic_texture = value,
+            6 => obj.use_roughness_texture = value,
+            7 => obj.use_ao_texture = value,
+            _ => return
+        }
+        if value {
+            obj.prepare_texture(texture_index);
+        }
+    }
+
+    pub fn set_object_texture_color_space(&mut self, object_handle: usize, texture_index: usize, color_space: ColorSpace) {
+        if !self.is_handle_exist(object_handle) { return }
+        if !(1..=7).contains(&texture_index) { return }
+
+        let obj = &mut self.objects[object_handle];
+        obj.texture_color_spaces[texture_index - 1] = color_space;
+        obj.decode_texture_border_color(texture_index);
+        if obj.is_texture_used(texture_index) {
+            obj.prepare_texture(texture_index);
+        }
+    }
+
+    pub fn set_object_texture_filter(&mut self, object_handle: usize, texture_index: usize, filter_id: u32) {
+        if !self.is_handle_exist(object_handle) { return }
+
+        if let (Some(sampler), Some(filter)) = (
+            self.objects[object_handle].get_texture_sampler(texture_index),
+            TextureFilter::from_id(filter_id)
+        ) {
+            sampler.filter = filter;
+        }
+    }
+
+    pub fn set_object_texture_wrap(&mut self, object_handle: usize, texture_index: usize, wrap_u_id: u32, wrap_v_id: u32) {
+        if !self.is_handle_exist(object_handle) { return }
+
+        if let (Some(sampler), Some(wrap_u), Some(wrap_v)) = (
+            self.objects[object_handle].get_texture_sampler(texture_index),
+            TextureWrap::from_id(wrap_u_id),
+            TextureWrap::from_id(wrap_v_id)
+        ) {
+            sampler.wrap_u = wrap_u;
+            sampler.wrap_v = wrap_v;
+        }
+    }
+
+    pub fn set_object_texture_border_color(&mut self, object_handle: usize, texture_index: usize, r: u8, g: u8, b: u8) {
+        if !self.is_handle_exist(object_handle) { return }
+
+        if !(1..=7).contains(&texture_index) { return }
+
+        let obj = &mut self.objects[object_handle];
+        obj.texture_border_colors[texture_index - 1] = Color { r, g, b };
+        obj.decode_texture_border_color(texture_index);
+    }
+
+    pub fn set_object_texture_offset(&mut self, object_handle: usize, texture_index: usize, u: f64, v: f64) {
+        if !self.is_handle_exist(object_handle) { return }
+
+        if let Some(sampler) = self.objects[object_handle].get_texture_sampler(texture_index) {
+            sampler.uv_offset = Vector2::new(u, v);
+        }
+    }
+
+    pub fn set_object_texture_scale(&mut self, object_handle: usize, texture_index: usize, u: f64, v: f64) {
+        if !self.is_handle_exist(object_handle) { return }
+
+        if let Some(sampler) = self.objects[object_handle].get_texture_sampler(texture_index) {
+            sampler.uv_scale = Vector2::new(u, v);
+        }
+    }
+
+    pub fn set_object_texture_rotation(&mut self, object_handle: usize, texture_index: usize, angle: f64) {
+        if !self.is_handle_exist(object_handle) { return }
+
+        if let Some(sampler) = self.objects[object_handle].get_texture_sampler(texture_index) {
+            sampler.uv_rotation = angle;
         }
     }
 
@@ -111,4 +298,26 @@ impl World {
 
         self.objects[object_handle].get_texture_pixels(texture_index)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn border_color(world: &mut World, texture_index: usize) -> Color<f64> {
+        world.objects[0].get_texture_sampler(texture_index).unwrap().border_color
+    }
+
+    #[test]
+    fn border_color_follows_slot_color_space() {
+        let mut world = World::new();
+        world.new_object();
+        // diffuse is srgb, roughness is linear
+        world.set_object_texture_border_color(0, 1, 128, 128, 128);
+        world.set_object_texture_border_color(0, 6, 128, 128, 128);
+        assert!((border_color(&mut world, 1).r - 0.2158).abs() < 1e-3);
+        assert!((border_color(&mut world, 6).r - 128. / 255.).abs() < 1e-9);
+
+        world.set_object_texture_color_space(0, 1, ColorSpace::Linear);
+        assert!((border_color(&mut world, 1).r - 128. / 255.).abs() < 1e-9);
+    }
+}